@@ -0,0 +1,65 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use glitter::processing::MemoryFileSystem;
+use glitter::Glitter;
+
+const TEMPLATE: &str =
+    "  LAYER\n    NAME \"*{name}\"\n    DATA \"*{data}\"\n    STATUS *{status}\n  END";
+
+/// A config with `iterations` injections, rendering `template`
+fn config(iterations: usize, template: &str) -> String {
+    let mut config = String::from("injection:\n");
+    for iteration in 0..iterations {
+        config.push_str(&format!(
+            "  - name: {{ value: \"layer {iteration}\" }}\n    data: {{ value: \"table_{iteration}\" }}\n    status: {{ value: \"ON\" }}\n"
+        ));
+    }
+    config.push_str(template);
+
+    config
+}
+
+fn inline_template(c: &mut Criterion) {
+    let mut group = c.benchmark_group("inline_template");
+    for iterations in [100, 1_000, 10_000] {
+        let glitter = Glitter::builder()
+            .config_str(config(
+                iterations,
+                &format!("template: {}\n", serde_json::to_string(TEMPLATE).unwrap()),
+            ))
+            .build()
+            .unwrap();
+
+        group.throughput(Throughput::Elements(iterations as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(iterations),
+            &glitter,
+            |b, glitter| b.iter(|| glitter.render_to_string().unwrap()),
+        );
+    }
+    group.finish();
+}
+
+fn quoted_template(c: &mut Criterion) {
+    let mut group = c.benchmark_group("quoted_template");
+    for iterations in [100, 1_000, 10_000] {
+        let glitter = Glitter::builder()
+            .config_str(config(
+                iterations,
+                "template:\n  body:\n    quote: layer.map\n",
+            ))
+            .file_system(MemoryFileSystem::new().with_file("layer.map", TEMPLATE))
+            .build()
+            .unwrap();
+
+        group.throughput(Throughput::Elements(iterations as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(iterations),
+            &glitter,
+            |b, glitter| b.iter(|| glitter.render_to_string().unwrap()),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, inline_template, quoted_template);
+criterion_main!(benches);
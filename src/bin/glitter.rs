@@ -1,32 +1,266 @@
 extern crate clap;
 
-use clap::Parser;
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use std::fs::File;
 use std::io::{self, BufRead, BufReader, BufWriter, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
-use glitter::{process, report};
+use glitter::config::model::{Newline, OutputFormat, TextEncoding};
+use glitter::processing::{AccessPolicy, DumpFormat};
+use glitter::rendering::output_files::{DirectoryOutput, OutputFiles};
+use glitter::rendering::value_format::parse_time;
+use glitter::{dump_variables, process_into, report, DumpOptions, ProcessOptions};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
+#[command(args_conflicts_with_subcommands = true)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// File name of the Input. `-` for stdin (default)
     input: Option<PathBuf>,
 
     /// File name of the Output. `-` for stdout (default)
     output: Option<PathBuf>,
+
+    /// Directory additional output files are written to.
+    /// Defaults to the directory of the Output, or the current directory for stdout
+    #[arg(long)]
+    output_root: Option<PathBuf>,
+
+    /// Number of `render`, `execute` and `load` values evaluated at the same time.
+    /// The output is the same as with one job
+    #[arg(long, short, default_value_t = 1, value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: u16,
+
+    /// Time of `$now` and `$today`, as RFC 3339, date or Unix seconds.
+    /// Defaults to SOURCE_DATE_EPOCH or the current time
+    #[arg(long, value_parser = parse_now)]
+    now: Option<SystemTime>,
+
+    #[command(flatten)]
+    format: FormatArgs,
+
+    #[command(flatten)]
+    policy: PolicyArgs,
+}
+
+fn parse_now(text: &str) -> Result<SystemTime, String> {
+    parse_time(text).ok_or_else(|| format!("{} is neither a date nor a time", text))
+}
+
+/// How the output is written, for all files whose glitter file doesn't say otherwise
+#[derive(Args)]
+struct FormatArgs {
+    /// Line breaks of the output. Defaults to those of the templates and values
+    #[arg(long, value_enum)]
+    newline: Option<NewlineArg>,
+
+    /// Character encoding of the output. Defaults to UTF-8
+    #[arg(long, value_enum)]
+    encoding: Option<EncodingArg>,
+
+    /// Start the output with a byte order mark (not written for Latin-1)
+    #[arg(long)]
+    bom: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum NewlineArg {
+    Lf,
+    Crlf,
+    Preserve,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum EncodingArg {
+    #[value(name = "utf-8")]
+    Utf8,
+    #[value(name = "utf-16le")]
+    Utf16Le,
+    #[value(name = "utf-16be")]
+    Utf16Be,
+    Latin1,
+}
+
+impl From<FormatArgs> for OutputFormat {
+    fn from(args: FormatArgs) -> Self {
+        OutputFormat {
+            newline: args.newline.map(|newline| match newline {
+                NewlineArg::Lf => Newline::Lf,
+                NewlineArg::Crlf => Newline::Crlf,
+                NewlineArg::Preserve => Newline::Preserve,
+            }),
+            bom: args.bom.then_some(true),
+            encoding: args.encoding.map(|encoding| match encoding {
+                EncodingArg::Utf8 => TextEncoding::Utf8,
+                EncodingArg::Utf16Le => TextEncoding::Utf16Le,
+                EncodingArg::Utf16Be => TextEncoding::Utf16Be,
+                EncodingArg::Latin1 => TextEncoding::Latin1,
+            }),
+        }
+    }
+}
+
+/// Restrictions for glitter files which are not fully trusted
+#[derive(Args)]
+struct PolicyArgs {
+    /// Refuse to run any `execute` or `shell`
+    #[arg(long)]
+    no_exec: bool,
+
+    /// Only run this executable (full path, or name looked up in PATH). Can be given multiple times
+    #[arg(long, value_name = "EXECUTABLE", conflicts_with = "no_exec")]
+    allow_exec: Vec<String>,
+
+    /// Only read files and run executables given as a path inside this directory, and write
+    /// output files inside the output root.
+    /// Absolute paths in glitter files are rejected
+    #[arg(long, value_name = "DIR")]
+    root: Option<PathBuf>,
+}
+
+impl From<PolicyArgs> for AccessPolicy {
+    fn from(args: PolicyArgs) -> Self {
+        AccessPolicy {
+            allow_exec: !args.no_exec,
+            allowed_executables: if args.allow_exec.is_empty() {
+                None
+            } else {
+                Some(args.allow_exec)
+            },
+            root: args.root,
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Print the fully resolved variables of global, local and each injection
+    Vars {
+        /// File name of the Input. `-` for stdin (default)
+        input: Option<PathBuf>,
+
+        /// File name of the Output. `-` for stdout (default)
+        output: Option<PathBuf>,
+
+        /// Structure format of the printed variables
+        #[arg(long, value_enum, default_value_t = VarsFormat::Yaml)]
+        format: VarsFormat,
+
+        /// Show `execute` and `render` as placeholders instead of evaluating them
+        #[arg(long)]
+        no_eval: bool,
+
+        /// Time of `$now` and `$today`, as RFC 3339, date or Unix seconds.
+        /// Defaults to SOURCE_DATE_EPOCH or the current time
+        #[arg(long, value_parser = parse_now)]
+        now: Option<SystemTime>,
+
+        #[command(flatten)]
+        policy: PolicyArgs,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum VarsFormat {
+    Yaml,
+    Json,
+}
+
+impl From<VarsFormat> for DumpFormat {
+    fn from(format: VarsFormat) -> Self {
+        match format {
+            VarsFormat::Yaml => DumpFormat::Yaml,
+            VarsFormat::Json => DumpFormat::Json,
+        }
+    }
 }
 
 fn main() {
     let cli = Cli::parse();
 
-    let input_path = cli.input.unwrap_or_else(|| PathBuf::from("-"));
-    let output_path = cli.output.unwrap_or_else(|| PathBuf::from("-"));
+    let result = match cli.command {
+        Some(Command::Vars {
+            input,
+            output,
+            format,
+            no_eval,
+            now,
+            policy,
+        }) => {
+            let (mut input_reader, filename, starting_directory) = open_input(input);
+            let mut output_writer = open_output(output);
+
+            let result = dump_variables(
+                &mut input_reader,
+                filename,
+                starting_directory,
+                &mut output_writer,
+                format.into(),
+                DumpOptions {
+                    evaluate_instructions: !no_eval,
+                    policy: policy.into(),
+                    now,
+                },
+            );
+            (result, output_writer)
+        }
+        None => {
+            let output_root = cli.output_root.unwrap_or_else(|| {
+                cli.output
+                    .as_deref()
+                    .filter(|path| !is_std_stream(path))
+                    .and_then(Path::parent)
+                    .map(Path::to_path_buf)
+                    .unwrap_or_else(|| PathBuf::from("."))
+            });
+            let mut output_files = DirectoryOutput::new(output_root);
+
+            let (mut input_reader, filename, starting_directory) = open_input(cli.input);
+            let mut output_writer = open_output(cli.output);
+
+            let result = process_into(
+                &mut input_reader,
+                filename,
+                starting_directory,
+                &mut output_writer,
+                &mut output_files,
+                ProcessOptions {
+                    policy: cli.policy.into(),
+                    jobs: cli.jobs.into(),
+                    output_format: cli.format.into(),
+                    now: cli.now,
+                },
+            );
+
+            for written_file in output_files.written_files() {
+                eprintln!("Wrote {}", written_file);
+            }
+            (result, output_writer)
+        }
+    };
+
+    let (result, mut output_writer) = result;
+    if let Err(error) = result {
+        report(&error);
+        std::process::exit(exitcode::SOFTWARE);
+    }
+
+    if output_writer.flush().is_err() {
+        eprintln!("Could not flush output. File might not contain all content");
+        std::process::exit(exitcode::IOERR);
+    }
+}
+
+fn is_std_stream(path: &Path) -> bool {
+    path == Path::new("-")
+}
 
-    let stdin = io::stdin();
-    let stdout = io::stdout();
-    let starting_directory: String;
-    let filename: String;
+fn open_input(input: Option<PathBuf>) -> (Box<dyn BufRead>, String, String) {
+    let input_path = input.unwrap_or_else(|| PathBuf::from("-"));
 
     let cwd = std::env::current_dir()
         .unwrap()
@@ -34,65 +268,54 @@ fn main() {
         .unwrap()
         .to_owned();
 
-    let mut input_reader: Box<dyn BufRead> = if input_path == PathBuf::from("-") {
-        filename = "-".to_owned();
-        starting_directory = cwd;
-        Box::new(stdin.lock())
+    if is_std_stream(&input_path) {
+        return (Box::new(io::stdin().lock()), "-".to_owned(), cwd);
+    }
+
+    let path = std::path::Path::new(&input_path);
+    let filename = if let Some(file) = path.file_name() {
+        file.to_str().unwrap().to_owned()
     } else {
-        let path = std::path::Path::new(&input_path);
-        filename = if let Some(file) = path.file_name() {
-            file.to_str().unwrap().to_owned()
-        } else {
-            eprintln!("INPUT must point to a valid file");
-            std::process::exit(exitcode::CONFIG);
-        };
-
-        starting_directory = if let Some(parent_dir) = path.parent() {
-            parent_dir
-                .to_str()
-                .expect(
-                    "No clue how this can happen that there is a file without any parent directory",
-                )
-                .to_owned()
-        } else {
-            cwd
-        };
-
-        let input_file = if let Ok(file) = File::open(input_path) {
-            file
-        } else {
-            eprintln!("Could not open input file");
-            std::process::exit(exitcode::NOINPUT);
-        };
-
-        Box::new(BufReader::new(input_file))
+        eprintln!("INPUT must point to a valid file");
+        std::process::exit(exitcode::CONFIG);
     };
 
-    let mut output_writer: Box<dyn Write> = if output_path == PathBuf::from("-") {
-        Box::new(stdout.lock())
+    let starting_directory = if let Some(parent_dir) = path.parent() {
+        parent_dir
+            .to_str()
+            .expect("No clue how this can happen that there is a file without any parent directory")
+            .to_owned()
     } else {
-        let output_file = if let Ok(file) = File::create(output_path) {
-            file
-        } else {
-            eprintln!("Could not open output file");
-            std::process::exit(exitcode::CANTCREAT);
-        };
-
-        Box::new(BufWriter::new(output_file))
+        cwd
     };
 
-    if let Err(error) = process(
-        &mut input_reader,
+    let input_file = if let Ok(file) = File::open(&input_path) {
+        file
+    } else {
+        eprintln!("Could not open input file");
+        std::process::exit(exitcode::NOINPUT);
+    };
+
+    (
+        Box::new(BufReader::new(input_file)),
         filename,
         starting_directory,
-        &mut output_writer,
-    ) {
-        report(&error);
-        std::process::exit(exitcode::SOFTWARE);
-    }
+    )
+}
 
-    if output_writer.flush().is_err() {
-        eprintln!("Could not flush output. File might not contain all content");
-        std::process::exit(exitcode::IOERR);
+fn open_output(output: Option<PathBuf>) -> Box<dyn Write> {
+    let output_path = output.unwrap_or_else(|| PathBuf::from("-"));
+
+    if is_std_stream(&output_path) {
+        return Box::new(io::stdout().lock());
     }
+
+    let output_file = if let Ok(file) = File::create(output_path) {
+        file
+    } else {
+        eprintln!("Could not open output file");
+        std::process::exit(exitcode::CANTCREAT);
+    };
+
+    Box::new(BufWriter::new(output_file))
 }
@@ -0,0 +1,389 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::SystemTime;
+
+use snafu::{OptionExt, ResultExt};
+
+use crate::config::document::DocumentFormat;
+use crate::config::model::{GlitterConfig, OutputFormat, RawValue, ValueDefinition};
+use crate::config::reader::ConfigReader;
+use crate::processing::{AccessPolicy, DiskFileSystem, FileSystem, GlitterProcessor};
+use crate::rendering::output_files::{DirectoryOutput, OutputFiles, SharedOutputFiles};
+use crate::rendering::template_rendering::{TemplateRendererConfig, DEFAULT_RENDERER_CONFIG};
+use crate::{
+    render_processors, ConfigFileUnreadableSnafu, ConfigSourceMissingSnafu, GlitterError,
+    InvalidConfigSnafu, OutputWriteFailedSnafu, RenderedTextInvalidSnafu,
+};
+
+#[cfg(feature = "tokio")]
+use crate::RenderTaskFailedSnafu;
+
+/// The `$filename` of configs given as string or as `GlitterConfig`
+const UNNAMED_CONFIG: &str = "(inline)";
+
+enum ConfigSource {
+    Text(String),
+    Path(PathBuf),
+    Config(Box<GlitterConfig>),
+}
+
+/// A glitter config ready to be rendered, created through `Glitter::builder()`:
+///
+/// ```no_run
+/// let text = glitter::Glitter::builder()
+///     .config_str("injection: [{ name: { value: World } }]\ntemplate: 'Hello *{name}'")
+///     .global("version", 3)
+///     .build()?
+///     .render_to_string()?;
+/// # Ok::<(), glitter::GlitterError>(())
+/// ```
+///
+/// It can be shared between threads (e.g. in an `Arc`), each render may override globals.
+pub struct Glitter {
+    configs: Vec<GlitterConfig>,
+    name: String,
+    directory: String,
+    policy: AccessPolicy,
+    file_system: Arc<dyn FileSystem>,
+    renderer_config: TemplateRendererConfig,
+    jobs: usize,
+    output_format: OutputFormat,
+    now: Option<SystemTime>,
+    output: Mutex<Option<Box<dyn Write + Send>>>,
+    output_files: Mutex<Box<dyn OutputFiles>>,
+}
+
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<Glitter>();
+};
+
+pub struct GlitterBuilder {
+    source: Option<ConfigSource>,
+    format: Option<DocumentFormat>,
+    name: Option<String>,
+    directory: Option<String>,
+    globals: BTreeMap<String, RawValue>,
+    policy: AccessPolicy,
+    file_system: Arc<dyn FileSystem>,
+    renderer_config: TemplateRendererConfig,
+    jobs: usize,
+    output_format: OutputFormat,
+    now: Option<SystemTime>,
+    output: Option<Box<dyn Write + Send>>,
+    output_files: Option<Box<dyn OutputFiles>>,
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Glitter {
+    pub fn builder() -> GlitterBuilder {
+        GlitterBuilder {
+            source: None,
+            format: None,
+            name: None,
+            directory: None,
+            globals: BTreeMap::new(),
+            policy: AccessPolicy::default(),
+            file_system: Arc::new(DiskFileSystem),
+            renderer_config: DEFAULT_RENDERER_CONFIG,
+            jobs: 1,
+            output_format: OutputFormat::default(),
+            now: None,
+            output: None,
+            output_files: None,
+        }
+    }
+
+    /// Renders into the output given to the builder, or stdout if none was given
+    pub fn render(&self) -> Result<(), GlitterError> {
+        match &mut *lock(&self.output) {
+            Some(output) => self.render_to(output),
+            None => self.render_to(&mut std::io::stdout().lock()),
+        }
+    }
+
+    pub fn render_to(&self, output: &mut dyn Write) -> Result<(), GlitterError> {
+        self.render_configs(self.configs.clone(), output)
+    }
+
+    /// Renders with additional globals, taking precedence over those given to the builder
+    pub fn render_to_with<K, V, I>(
+        &self,
+        globals: I,
+        output: &mut dyn Write,
+    ) -> Result<(), GlitterError>
+    where
+        K: Into<String>,
+        V: Into<RawValue>,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut configs = self.configs.clone();
+        override_globals(&mut configs, globals);
+
+        self.render_configs(configs, output)
+    }
+
+    pub fn render_to_string(&self) -> Result<String, GlitterError> {
+        let mut buffer = Vec::<u8>::new();
+        self.render_to(&mut buffer)?;
+
+        String::from_utf8(buffer).context(RenderedTextInvalidSnafu)
+    }
+
+    pub fn render_to_string_with<K, V, I>(&self, globals: I) -> Result<String, GlitterError>
+    where
+        K: Into<String>,
+        V: Into<RawValue>,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let mut buffer = Vec::<u8>::new();
+        self.render_to_with(globals, &mut buffer)?;
+
+        String::from_utf8(buffer).context(RenderedTextInvalidSnafu)
+    }
+
+    /// All additional files written so far
+    pub fn written_files(&self) -> Vec<String> {
+        lock(&self.output_files).written_files()
+    }
+
+    fn render_configs(
+        &self,
+        configs: Vec<GlitterConfig>,
+        output: &mut dyn Write,
+    ) -> Result<(), GlitterError> {
+        let processors = GlitterProcessor::stream(
+            self.name.clone(),
+            self.directory.clone(),
+            configs,
+            self.policy.clone(),
+            Arc::clone(&self.file_system),
+            self.now,
+        );
+        render_processors(
+            processors,
+            output,
+            &mut SharedOutputFiles(&self.output_files),
+            &self.renderer_config,
+            self.jobs,
+            &self.output_format,
+        )?;
+
+        output.flush().context(OutputWriteFailedSnafu)
+    }
+}
+
+/// Rendering reads files and may run commands, so it runs on the blocking thread pool
+#[cfg(feature = "tokio")]
+impl Glitter {
+    pub async fn render_to_string_async(self: Arc<Self>) -> Result<String, GlitterError> {
+        tokio::task::spawn_blocking(move || self.render_to_string())
+            .await
+            .context(RenderTaskFailedSnafu)?
+    }
+
+    pub async fn render_to_string_with_async<K, V, I>(
+        self: Arc<Self>,
+        globals: I,
+    ) -> Result<String, GlitterError>
+    where
+        K: Into<String>,
+        V: Into<RawValue>,
+        I: IntoIterator<Item = (K, V)>,
+    {
+        let globals = globals
+            .into_iter()
+            .map(|(key, value)| (key.into(), value.into()))
+            .collect::<Vec<(String, RawValue)>>();
+
+        tokio::task::spawn_blocking(move || self.render_to_string_with(globals))
+            .await
+            .context(RenderTaskFailedSnafu)?
+    }
+}
+
+/// The first definition of a global wins within a stream, so they go into the first document
+fn override_globals<K, V, I>(configs: &mut [GlitterConfig], globals: I)
+where
+    K: Into<String>,
+    V: Into<RawValue>,
+    I: IntoIterator<Item = (K, V)>,
+{
+    for (key, value) in globals {
+        configs[0]
+            .global
+            .insert(key.into(), ValueDefinition::Value(value.into()));
+    }
+}
+
+impl GlitterBuilder {
+    /// The config as text, in YAML unless another `format` is set
+    pub fn config_str<S: Into<String>>(mut self, config: S) -> Self {
+        self.source = Some(ConfigSource::Text(config.into()));
+        self
+    }
+
+    /// The config file, its format is chosen by its extension unless `format` is set.
+    /// Its directory is the base for relative paths.
+    pub fn config_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.source = Some(ConfigSource::Path(path.as_ref().to_path_buf()));
+        self
+    }
+
+    pub fn config(mut self, config: GlitterConfig) -> Self {
+        self.source = Some(ConfigSource::Config(Box::new(config)));
+        self
+    }
+
+    pub fn format(mut self, format: DocumentFormat) -> Self {
+        self.format = Some(format);
+        self
+    }
+
+    /// The name of the config, available as `$filename`
+    pub fn name<S: Into<String>>(mut self, name: S) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// The directory relative paths are resolved against, by default the directory of
+    /// the config file or the current directory
+    pub fn directory<S: Into<String>>(mut self, directory: S) -> Self {
+        self.directory = Some(directory.into());
+        self
+    }
+
+    /// A global variable, taking precedence over the `global` section of the config
+    pub fn global<K: Into<String>, V: Into<RawValue>>(mut self, key: K, value: V) -> Self {
+        self.globals.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn globals<K: Into<String>, V: Into<RawValue>, I: IntoIterator<Item = (K, V)>>(
+        mut self,
+        globals: I,
+    ) -> Self {
+        for (key, value) in globals {
+            self.globals.insert(key.into(), value.into());
+        }
+        self
+    }
+
+    pub fn policy(mut self, policy: AccessPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Where all files are read from, including the config file, by default the disk
+    pub fn file_system<F: FileSystem + 'static>(mut self, file_system: F) -> Self {
+        self.file_system = Arc::new(file_system);
+        self
+    }
+
+    /// Markers used to find processing blocks in templates
+    pub fn renderer_config(mut self, renderer_config: TemplateRendererConfig) -> Self {
+        self.renderer_config = renderer_config;
+        self
+    }
+
+    /// How many `render`, `execute` and `load` values are evaluated at the same time,
+    /// by default one after another
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = jobs;
+        self
+    }
+
+    /// Newlines, encoding and BOM of the output and of all files whose config doesn't set its
+    /// own, by default the text is written as rendered
+    pub fn output_format(mut self, output_format: OutputFormat) -> Self {
+        self.output_format = output_format;
+        self
+    }
+
+    /// The time of `$now` and `$today`, e.g. to render the same text again later. By default
+    /// `SOURCE_DATE_EPOCH` or the current time of each render.
+    pub fn now(mut self, now: SystemTime) -> Self {
+        self.now = Some(now);
+        self
+    }
+
+    /// Where `Glitter::render` writes to
+    pub fn output<W: Write + Send + 'static>(mut self, output: W) -> Self {
+        self.output = Some(Box::new(output));
+        self
+    }
+
+    /// Where additional files (`outputs`, documents and sub-renders with `output`) are
+    /// created, by default the config's directory
+    pub fn output_files<F: OutputFiles + 'static>(mut self, output_files: F) -> Self {
+        self.output_files = Some(Box::new(output_files));
+        self
+    }
+
+    /// Reads the config, the config file is not read again when rendering
+    pub fn build(self) -> Result<Glitter, GlitterError> {
+        let source = self.source.context(ConfigSourceMissingSnafu)?;
+
+        let (mut configs, default_name, default_directory) = match source {
+            ConfigSource::Text(text) => {
+                let reader = ConfigReader::for_format(self.format.unwrap_or_default());
+                let configs = reader
+                    .read_stream(&mut text.as_bytes())
+                    .context(InvalidConfigSnafu)?;
+                (configs, String::from(UNNAMED_CONFIG), String::from("."))
+            }
+            ConfigSource::Path(path) => {
+                let mut input =
+                    self.file_system
+                        .open(&path)
+                        .context(ConfigFileUnreadableSnafu {
+                            path: path.to_string_lossy(),
+                        })?;
+                let reader = ConfigReader::for_format(DocumentFormat::resolve(self.format, &path));
+                let configs = reader.read_stream(&mut input).context(InvalidConfigSnafu)?;
+
+                let name = path
+                    .file_name()
+                    .map(|name| name.to_string_lossy().into_owned())
+                    .unwrap_or_else(|| String::from(UNNAMED_CONFIG));
+                let directory = path
+                    .parent()
+                    .map(|parent| parent.to_string_lossy().into_owned())
+                    .filter(|parent| !parent.is_empty())
+                    .unwrap_or_else(|| String::from("."));
+                (configs, name, directory)
+            }
+            ConfigSource::Config(config) => (
+                vec![*config],
+                String::from(UNNAMED_CONFIG),
+                String::from("."),
+            ),
+        };
+
+        override_globals(&mut configs, self.globals);
+
+        let directory = self.directory.unwrap_or(default_directory);
+        let output_files = self
+            .output_files
+            .unwrap_or_else(|| Box::new(DirectoryOutput::new(&directory)));
+
+        Ok(Glitter {
+            configs,
+            name: self.name.unwrap_or(default_name),
+            directory,
+            policy: self.policy,
+            file_system: self.file_system,
+            renderer_config: self.renderer_config,
+            jobs: self.jobs,
+            output_format: self.output_format,
+            now: self.now,
+            output: Mutex::new(self.output),
+            output_files: Mutex::new(output_files),
+        })
+    }
+}
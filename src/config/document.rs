@@ -0,0 +1,307 @@
+use std::backtrace::Backtrace;
+use std::fmt::{Display, Formatter};
+use std::ops::Index;
+use std::path::Path;
+
+use snafu::{ResultExt, Snafu};
+use yaml_rust::{yaml::Hash, Yaml, YamlEmitter, YamlLoader};
+
+use crate::config::flat_formats::{parse_dotenv, parse_ini, parse_properties};
+
+#[derive(Debug, Snafu)]
+#[snafu(visibility(pub(crate)))]
+pub enum DocumentReadError {
+    #[snafu(display("Invalid YAML document"))]
+    InvalidYamlDocument {
+        #[snafu(source(from(yaml_rust::ScanError, Box::new)))]
+        source: Box<yaml_rust::ScanError>,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Invalid JSON document"))]
+    InvalidJsonDocument {
+        #[snafu(source(from(serde_json::Error, Box::new)))]
+        source: Box<serde_json::Error>,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Invalid TOML document"))]
+    InvalidTomlDocument {
+        #[snafu(source(from(toml::de::Error, Box::new)))]
+        source: Box<toml::de::Error>,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Malformed {} input in line {}: {}", format_name, line, reason))]
+    MalformedLine {
+        format_name: &'static str,
+        line: usize,
+        reason: String,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Unknown document format {}", format_name))]
+    UnknownDocumentFormat {
+        format_name: String,
+        backtrace: Backtrace,
+    },
+}
+
+/// The serialization formats a document can be read from
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DocumentFormat {
+    #[default]
+    Yaml,
+    Json,
+    Toml,
+    /// `.env` files, only containing string values
+    Dotenv,
+    /// `.ini` files, only containing sections and string values
+    Ini,
+    /// Java `.properties` files, only containing string values
+    Properties,
+}
+
+impl DocumentFormat {
+    pub fn from_name(format_name: &str) -> Result<Self, DocumentReadError> {
+        match format_name.to_lowercase().as_str() {
+            "yaml" | "yml" => Ok(DocumentFormat::Yaml),
+            "json" => Ok(DocumentFormat::Json),
+            "toml" => Ok(DocumentFormat::Toml),
+            "env" | "dotenv" => Ok(DocumentFormat::Dotenv),
+            "ini" => Ok(DocumentFormat::Ini),
+            "properties" => Ok(DocumentFormat::Properties),
+            _ => UnknownDocumentFormatSnafu { format_name }.fail(),
+        }
+    }
+
+    /// Guesses the format by the file extension. Unknown extensions (like `.glitter`) yield `None`
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Option<Self> {
+        let path = path.as_ref();
+        if path.file_name().and_then(|name| name.to_str()) == Some(".env") {
+            return Some(DocumentFormat::Dotenv);
+        }
+
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .and_then(|extension| Self::from_name(extension).ok())
+    }
+
+    /// The explicitly requested format, else the one matching the file extension, else YAML
+    pub fn resolve<P: AsRef<Path>>(explicit: Option<DocumentFormat>, path: P) -> Self {
+        explicit
+            .or_else(|| Self::from_path(path))
+            .unwrap_or_default()
+    }
+}
+
+/// Format neutral representation of a parsed configuration or data file
+#[derive(Clone, Debug, PartialEq)]
+pub enum Document {
+    String(String),
+    Integer(i64),
+    Float(String),
+    Boolean(bool),
+    Array(Vec<Document>),
+    Map(Vec<(Document, Document)>),
+    Null,
+    BadValue,
+}
+
+static BAD_VALUE: Document = Document::BadValue;
+
+impl Document {
+    /// Parses all documents contained in the given content.
+    /// Only YAML supports streams of multiple documents, all other formats always return one.
+    pub fn parse_stream(
+        content: &str,
+        format: DocumentFormat,
+    ) -> Result<Vec<Document>, DocumentReadError> {
+        match format {
+            DocumentFormat::Yaml => Ok(YamlLoader::load_from_str(content)
+                .context(InvalidYamlDocumentSnafu)?
+                .iter()
+                .map(Document::from)
+                .collect()),
+            DocumentFormat::Json => {
+                let value: serde_json::Value =
+                    serde_json::from_str(content).context(InvalidJsonDocumentSnafu)?;
+                Ok(vec![Document::from(&value)])
+            }
+            DocumentFormat::Toml => {
+                let table: toml::Table =
+                    toml::from_str(content).context(InvalidTomlDocumentSnafu)?;
+                Ok(vec![Document::from(&toml::Value::Table(table))])
+            }
+            DocumentFormat::Dotenv => Ok(vec![parse_dotenv(content)?]),
+            DocumentFormat::Ini => Ok(vec![parse_ini(content)?]),
+            DocumentFormat::Properties => Ok(vec![parse_properties(content)?]),
+        }
+    }
+
+    /// Parses the first document of the given content. Empty content results in `Null`
+    pub fn parse(content: &str, format: DocumentFormat) -> Result<Document, DocumentReadError> {
+        Ok(Self::parse_stream(content, format)?
+            .into_iter()
+            .next()
+            .unwrap_or(Document::Null))
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Document::String(string_value) => Some(string_value),
+            _ => None,
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        *self == Document::Null
+    }
+
+    pub fn is_badvalue(&self) -> bool {
+        *self == Document::BadValue
+    }
+
+    pub fn get(&self, key: &str) -> Option<&Document> {
+        if let Document::Map(entries) = self {
+            entries
+                .iter()
+                .find(|(entry_key, _)| entry_key.as_str() == Some(key))
+                .map(|(_, value)| value)
+        } else {
+            None
+        }
+    }
+}
+
+impl Index<&str> for Document {
+    type Output = Document;
+
+    fn index(&self, key: &str) -> &Self::Output {
+        self.get(key).unwrap_or(&BAD_VALUE)
+    }
+}
+
+impl Display for Document {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut rendered = String::new();
+        if YamlEmitter::new(&mut rendered)
+            .dump(&Yaml::from(self))
+            .is_err()
+        {
+            return f.write_str("(INVALID DOCUMENT)");
+        }
+
+        f.write_str(&rendered)
+    }
+}
+
+impl From<&Yaml> for Document {
+    fn from(yaml: &Yaml) -> Self {
+        match yaml {
+            Yaml::String(string_value) => Document::String(string_value.clone()),
+            Yaml::Integer(int_value) => Document::Integer(*int_value),
+            Yaml::Real(real_as_string) => Document::Float(real_as_string.clone()),
+            Yaml::Boolean(bool_value) => Document::Boolean(*bool_value),
+            Yaml::Array(array) => Document::Array(array.iter().map(Document::from).collect()),
+            Yaml::Hash(hash) => Document::Map(convert_yaml_hash(hash)),
+            Yaml::Null => Document::Null,
+            Yaml::Alias(_) | Yaml::BadValue => Document::BadValue,
+        }
+    }
+}
+
+const MERGE_KEY: &str = "<<";
+
+/// Converts the entries of a YAML mapping, honouring merge keys (`<<: *base` or `<<: [*a, *b]`).
+/// Explicitly defined keys always win over merged ones, earlier merge sources over later ones.
+fn convert_yaml_hash(hash: &Hash) -> Vec<(Document, Document)> {
+    let mut entries = hash
+        .iter()
+        .filter(|(key, _)| key.as_str() != Some(MERGE_KEY))
+        .map(|(key, value)| (Document::from(key), Document::from(value)))
+        .collect::<Vec<_>>();
+
+    let merge_sources = match hash.get(&Yaml::String(String::from(MERGE_KEY))) {
+        Some(Yaml::Hash(source)) => vec![source],
+        Some(Yaml::Array(sources)) => sources
+            .iter()
+            .filter_map(|source| match source {
+                Yaml::Hash(source) => Some(source),
+                _ => None,
+            })
+            .collect(),
+        _ => vec![],
+    };
+
+    for source in merge_sources {
+        for (key, value) in convert_yaml_hash(source) {
+            if !entries.iter().any(|(existing, _)| *existing == key) {
+                entries.push((key, value));
+            }
+        }
+    }
+
+    entries
+}
+
+impl From<&Document> for Yaml {
+    fn from(document: &Document) -> Self {
+        match document {
+            Document::String(string_value) => Yaml::String(string_value.clone()),
+            Document::Integer(int_value) => Yaml::Integer(*int_value),
+            Document::Float(real_as_string) => Yaml::Real(real_as_string.clone()),
+            Document::Boolean(bool_value) => Yaml::Boolean(*bool_value),
+            Document::Array(array) => Yaml::Array(array.iter().map(Yaml::from).collect()),
+            Document::Map(entries) => Yaml::Hash(
+                entries
+                    .iter()
+                    .map(|(key, value)| (Yaml::from(key), Yaml::from(value)))
+                    .collect::<Hash>(),
+            ),
+            Document::Null => Yaml::Null,
+            Document::BadValue => Yaml::BadValue,
+        }
+    }
+}
+
+impl From<&serde_json::Value> for Document {
+    fn from(value: &serde_json::Value) -> Self {
+        match value {
+            serde_json::Value::String(string_value) => Document::String(string_value.clone()),
+            serde_json::Value::Number(number) => match number.as_i64() {
+                Some(int_value) => Document::Integer(int_value),
+                None => Document::Float(number.to_string()),
+            },
+            serde_json::Value::Bool(bool_value) => Document::Boolean(*bool_value),
+            serde_json::Value::Array(array) => {
+                Document::Array(array.iter().map(Document::from).collect())
+            }
+            serde_json::Value::Object(object) => Document::Map(
+                object
+                    .iter()
+                    .map(|(key, value)| (Document::String(key.clone()), Document::from(value)))
+                    .collect(),
+            ),
+            serde_json::Value::Null => Document::Null,
+        }
+    }
+}
+
+impl From<&toml::Value> for Document {
+    fn from(value: &toml::Value) -> Self {
+        match value {
+            toml::Value::String(string_value) => Document::String(string_value.clone()),
+            toml::Value::Integer(int_value) => Document::Integer(*int_value),
+            // `Debug` keeps the fraction of whole numbers: `1.0` as read from YAML
+            toml::Value::Float(float_value) => Document::Float(format!("{float_value:?}")),
+            toml::Value::Boolean(bool_value) => Document::Boolean(*bool_value),
+            toml::Value::Datetime(datetime) => Document::String(datetime.to_string()),
+            toml::Value::Array(array) => {
+                Document::Array(array.iter().map(Document::from).collect())
+            }
+            toml::Value::Table(table) => Document::Map(
+                table
+                    .iter()
+                    .map(|(key, value)| (Document::String(key.clone()), Document::from(value)))
+                    .collect(),
+            ),
+        }
+    }
+}
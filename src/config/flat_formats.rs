@@ -0,0 +1,477 @@
+use crate::config::document::{Document, DocumentReadError, MalformedLineSnafu};
+
+/// Key/value tree built while reading a line based format
+enum Entry {
+    Value(String),
+    Section(Vec<(String, Entry)>),
+}
+
+impl Entry {
+    fn into_document(self) -> Document {
+        match self {
+            Entry::Value(value) => Document::String(value),
+            Entry::Section(entries) => Document::Map(
+                entries
+                    .into_iter()
+                    .map(|(key, entry)| (Document::String(key), entry.into_document()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+struct EntryTree {
+    format_name: &'static str,
+    root: Vec<(String, Entry)>,
+}
+
+impl EntryTree {
+    fn new(format_name: &'static str) -> Self {
+        EntryTree {
+            format_name,
+            root: vec![],
+        }
+    }
+
+    fn malformed<T>(&self, line: usize, reason: &str) -> Result<T, DocumentReadError> {
+        MalformedLineSnafu {
+            format_name: self.format_name,
+            line,
+            reason,
+        }
+        .fail()
+    }
+
+    /// Makes sure the section exists, creating all missing parents on the way
+    fn ensure_section(&mut self, path: &[&str], line: usize) -> Result<(), DocumentReadError> {
+        self.section_mut(path, line).map(|_| ())
+    }
+
+    /// Inserts the value at the given path. Later definitions of the same key replace earlier ones
+    fn insert(
+        &mut self,
+        path: &[&str],
+        value: String,
+        line: usize,
+    ) -> Result<(), DocumentReadError> {
+        let (key, parents) = path.split_last().expect("a key path is never empty");
+        let section = self.section_mut(parents, line)?;
+
+        match section.iter_mut().find(|(existing, _)| existing == key) {
+            Some((_, Entry::Section(_))) => {
+                return self.malformed(
+                    line,
+                    &format!(
+                        "`{}` already holds nested keys, dotted keys are nested so it can't \
+                         have a value too",
+                        key
+                    ),
+                )
+            }
+            Some((_, existing)) => *existing = Entry::Value(value),
+            None => section.push((key.to_string(), Entry::Value(value))),
+        }
+
+        Ok(())
+    }
+
+    fn section_mut(
+        &mut self,
+        path: &[&str],
+        line: usize,
+    ) -> Result<&mut Vec<(String, Entry)>, DocumentReadError> {
+        let format_name = self.format_name;
+        let mut section = &mut self.root;
+
+        for part in path {
+            let index = match section.iter().position(|(key, _)| key == part) {
+                Some(index) => index,
+                None => {
+                    section.push((part.to_string(), Entry::Section(vec![])));
+                    section.len() - 1
+                }
+            };
+
+            section = match &mut section[index].1 {
+                Entry::Section(entries) => entries,
+                Entry::Value(_) => {
+                    return MalformedLineSnafu {
+                        format_name,
+                        line,
+                        reason: format!(
+                            "`{}` already has a value, dotted keys are nested so it can't \
+                             hold nested keys too",
+                            part
+                        ),
+                    }
+                    .fail()
+                }
+            };
+        }
+
+        Ok(section)
+    }
+
+    fn into_document(self) -> Document {
+        Entry::Section(self.root).into_document()
+    }
+}
+
+fn split_path(key: &str) -> Vec<&str> {
+    key.split('.').map(str::trim).collect()
+}
+
+fn is_valid_path(path: &[&str]) -> bool {
+    path.iter().all(|part| !part.is_empty())
+}
+
+/// Reads `.env` files: `KEY=value` per line, optionally prefixed with `export`.
+/// Values may be single quoted (taken literally) or double quoted (backslash escapes are resolved).
+pub(crate) fn parse_dotenv(content: &str) -> Result<Document, DocumentReadError> {
+    let mut tree = EntryTree::new("dotenv");
+
+    for (index, raw_line) in content.lines().enumerate() {
+        let line_number = index + 1;
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let line = line
+            .strip_prefix("export ")
+            .map(str::trim_start)
+            .unwrap_or(line);
+        let Some((key, raw_value)) = line.split_once('=') else {
+            return tree.malformed(line_number, "expected `KEY=value`");
+        };
+
+        let key = key.trim();
+        if key.is_empty()
+            || !key
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return tree.malformed(line_number, &format!("invalid variable name `{}`", key));
+        }
+
+        let value = dotenv_value(raw_value.trim())
+            .or_else(|reason| tree.malformed(line_number, &reason))?;
+        tree.insert(&[key], value, line_number)?;
+    }
+
+    Ok(tree.into_document())
+}
+
+fn dotenv_value(raw_value: &str) -> Result<String, String> {
+    if let Some(quoted) = raw_value.strip_prefix('\'') {
+        let Some(end) = quoted.find('\'') else {
+            return Err(String::from("unterminated single quoted value"));
+        };
+        return Ok(quoted[..end].to_owned());
+    }
+
+    if let Some(quoted) = raw_value.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = quoted.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '"' => return Ok(value),
+                '\\' => match chars.next() {
+                    Some('n') => value.push('\n'),
+                    Some('r') => value.push('\r'),
+                    Some('t') => value.push('\t'),
+                    Some(other) => value.push(other),
+                    None => break,
+                },
+                other => value.push(other),
+            }
+        }
+        return Err(String::from("unterminated double quoted value"));
+    }
+
+    // unquoted values end at an inline comment
+    let value = match raw_value.find(" #") {
+        Some(comment_start) => &raw_value[..comment_start],
+        None => raw_value,
+    };
+    Ok(value.trim_end().to_owned())
+}
+
+/// Reads `.ini` files. Every `[section]` becomes an object, dotted section or key names nest further.
+/// Keys are separated from their value by `=` or `:`, comments start with `;` or `#`, also
+/// after whitespace behind a value.
+pub(crate) fn parse_ini(content: &str) -> Result<Document, DocumentReadError> {
+    let mut tree = EntryTree::new("INI");
+    let mut current_section: Vec<String> = vec![];
+
+    for (index, raw_line) in content.lines().enumerate() {
+        let line_number = index + 1;
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let Some(name) = header.strip_suffix(']') else {
+                return tree.malformed(line_number, "unterminated section header");
+            };
+            let path = split_path(name);
+            if !is_valid_path(&path) {
+                return tree.malformed(line_number, &format!("invalid section name `{}`", name));
+            }
+
+            tree.ensure_section(&path, line_number)?;
+            current_section = path.into_iter().map(str::to_owned).collect();
+            continue;
+        }
+
+        let Some(separator) = line.find(['=', ':']) else {
+            return tree.malformed(line_number, "expected `key = value`");
+        };
+        let key = line[..separator].trim();
+        let key_path = split_path(key);
+        if !is_valid_path(&key_path) {
+            return tree.malformed(line_number, &format!("invalid key `{}`", key));
+        }
+
+        let value = ini_value(line[separator + 1..].trim());
+        let path = current_section
+            .iter()
+            .map(String::as_str)
+            .chain(key_path)
+            .collect::<Vec<_>>();
+        tree.insert(&path, value, line_number)?;
+    }
+
+    Ok(tree.into_document())
+}
+
+fn ini_value(raw_value: &str) -> String {
+    for quote in ['"', '\''] {
+        let Some((quoted, rest)) = raw_value
+            .strip_prefix(quote)
+            .and_then(|quoted| quoted.split_once(quote))
+        else {
+            continue;
+        };
+        if is_ini_comment(rest.trim_start()) {
+            return quoted.to_owned();
+        }
+    }
+
+    // unquoted values end at an inline comment, which follows whitespace
+    let comment_start = raw_value
+        .char_indices()
+        .find(|&(position, _)| {
+            raw_value[..position].ends_with(char::is_whitespace)
+                && is_ini_comment(&raw_value[position..])
+        })
+        .map_or(raw_value.len(), |(position, _)| position);
+    raw_value[..comment_start].trim_end().to_owned()
+}
+
+fn is_ini_comment(text: &str) -> bool {
+    text.is_empty() || text.starts_with([';', '#'])
+}
+
+/// Reads Java `.properties` files, including line continuations and `\uXXXX` escapes.
+/// Dotted keys like `db.host` are nested, so they can be accessed as `*{props.db.host}`.
+pub(crate) fn parse_properties(content: &str) -> Result<Document, DocumentReadError> {
+    let mut tree = EntryTree::new("properties");
+    let mut lines = content.lines().enumerate();
+
+    while let Some((index, raw_line)) = lines.next() {
+        let line_number = index + 1;
+        let line = raw_line.trim_start();
+        if line.is_empty() || line.starts_with('#') || line.starts_with('!') {
+            continue;
+        }
+
+        // join continued lines: an odd number of trailing backslashes escapes the line break
+        let mut logical_line = line.to_owned();
+        while ends_with_continuation(&logical_line) {
+            logical_line.pop();
+            match lines.next() {
+                Some((_, next_line)) => logical_line.push_str(next_line.trim_start()),
+                None => break,
+            }
+        }
+
+        let (raw_key, raw_value) = split_property(&logical_line);
+        let key =
+            unescape_property(raw_key).or_else(|reason| tree.malformed(line_number, &reason))?;
+        let value =
+            unescape_property(raw_value).or_else(|reason| tree.malformed(line_number, &reason))?;
+
+        let key_path = split_path(&key);
+        if !is_valid_path(&key_path) {
+            return tree.malformed(line_number, &format!("invalid key `{}`", key));
+        }
+        tree.insert(&key_path, value, line_number)?;
+    }
+
+    Ok(tree.into_document())
+}
+
+fn ends_with_continuation(line: &str) -> bool {
+    line.chars().rev().take_while(|&c| c == '\\').count() % 2 == 1
+}
+
+/// Splits at the first unescaped `=`, `:` or whitespace
+fn split_property(line: &str) -> (&str, &str) {
+    let mut escaped = false;
+    for (position, c) in line.char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+
+        match c {
+            '\\' => escaped = true,
+            '=' | ':' => return (&line[..position], line[position + 1..].trim_start()),
+            c if c.is_whitespace() => {
+                let rest = line[position..].trim_start();
+                let rest = rest
+                    .strip_prefix(['=', ':'])
+                    .map(str::trim_start)
+                    .unwrap_or(rest);
+                return (&line[..position], rest);
+            }
+            _ => (),
+        }
+    }
+
+    (line, "")
+}
+
+/// The character of a `\uXXXX` escape, characters outside of the BMP are written as a
+/// pair of UTF-16 surrogates: `\uD83D\uDE00`
+fn unescape_unicode(chars: &mut std::str::Chars) -> Result<char, String> {
+    let high = utf16_unit(chars)?;
+    if !(0xD800..0xDC00).contains(&high) {
+        return char::from_u32(high)
+            .ok_or_else(|| format!("invalid unicode escape `\\u{:04X}`", high));
+    }
+
+    let low = match (chars.next(), chars.next()) {
+        (Some('\\'), Some('u')) => utf16_unit(chars)?,
+        _ => return Err(format!("unpaired surrogate `\\u{:04X}`", high)),
+    };
+    char::decode_utf16([high as u16, low as u16])
+        .next()
+        .and_then(Result::ok)
+        .ok_or_else(|| format!("invalid surrogate pair `\\u{:04X}\\u{:04X}`", high, low))
+}
+
+/// The four hex digits following `\u`
+fn utf16_unit(chars: &mut std::str::Chars) -> Result<u32, String> {
+    let code = chars.by_ref().take(4).collect::<String>();
+    u32::from_str_radix(&code, 16)
+        .ok()
+        .filter(|_| code.len() == 4 && code.chars().all(|c| c.is_ascii_hexdigit()))
+        .ok_or_else(|| format!("invalid unicode escape `\\u{}`", code))
+}
+
+fn unescape_property(raw: &str) -> Result<String, String> {
+    let mut result = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some('f') => result.push('\u{c}'),
+            Some('u') => result.push(unescape_unicode(&mut chars)?),
+            Some(other) => result.push(other),
+            None => (),
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(entries: &[(&str, Document)]) -> Document {
+        Document::Map(
+            entries
+                .iter()
+                .map(|(key, value)| (Document::String(key.to_string()), value.clone()))
+                .collect(),
+        )
+    }
+
+    fn string(value: &str) -> Document {
+        Document::String(value.to_owned())
+    }
+
+    #[test]
+    fn dotenv_quotes_and_comments() {
+        let document =
+            parse_dotenv("export A=plain # comment\nB='x # y'\nC=\"line\\nnext\"\n# D=skipped\n")
+                .unwrap();
+        assert_eq!(
+            document,
+            map(&[
+                ("A", string("plain")),
+                ("B", string("x # y")),
+                ("C", string("line\nnext")),
+            ])
+        );
+        assert!(parse_dotenv("A=\"open\n").is_err());
+        assert!(parse_dotenv("A B=1\n").is_err());
+    }
+
+    #[test]
+    fn ini_sections_nest_and_comments_need_whitespace() {
+        let document =
+            parse_ini("[db.main]\nhost = a;b ; comment\nname: 'x ; y' # quoted\n").unwrap();
+        assert_eq!(
+            document,
+            map(&[(
+                "db",
+                map(&[(
+                    "main",
+                    map(&[("host", string("a;b")), ("name", string("x ; y"))])
+                )])
+            )])
+        );
+        assert!(parse_ini("[open\n").is_err());
+    }
+
+    #[test]
+    fn properties_combine_surrogate_pairs() {
+        let document = parse_properties("smile = \\uD83D\\uDE00 \\u00e9\n").unwrap();
+        assert_eq!(document, map(&[("smile", string("\u{1F600} \u{e9}"))]));
+    }
+
+    #[test]
+    fn properties_reject_unpaired_surrogates() {
+        let error = parse_properties("broken = \\uD83Dx\n").unwrap_err();
+        assert!(
+            error.to_string().contains("unpaired surrogate"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn properties_explain_a_value_with_nested_keys() {
+        for content in ["a=x\na.b=y\n", "a.b=y\na=x\n"] {
+            let error = parse_properties(content).unwrap_err();
+            assert!(
+                error.to_string().contains("dotted keys are nested"),
+                "{}",
+                error
+            );
+        }
+    }
+}
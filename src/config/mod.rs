@@ -1,3 +1,5 @@
+pub mod document;
+mod flat_formats;
 pub mod model;
 pub mod reader;
 pub mod yaml_import;
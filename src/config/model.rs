@@ -1,5 +1,8 @@
 use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
+use std::time::Duration;
+
+use crate::config::document::DocumentFormat;
 
 #[derive(Clone, Debug)]
 pub enum RawValue {
@@ -19,22 +22,230 @@ impl Display for RawValue {
     }
 }
 
+impl From<bool> for RawValue {
+    fn from(value: bool) -> Self {
+        RawValue::Boolean(value)
+    }
+}
+
+impl From<i64> for RawValue {
+    fn from(value: i64) -> Self {
+        RawValue::Integer(value)
+    }
+}
+
+impl From<i32> for RawValue {
+    fn from(value: i32) -> Self {
+        RawValue::Integer(value.into())
+    }
+}
+
+impl From<f64> for RawValue {
+    fn from(value: f64) -> Self {
+        RawValue::Float(value.to_string())
+    }
+}
+
+impl From<&str> for RawValue {
+    fn from(value: &str) -> Self {
+        RawValue::String(value.to_owned())
+    }
+}
+
+impl From<String> for RawValue {
+    fn from(value: String) -> Self {
+        RawValue::String(value)
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct LoadStatement {
     pub file: String,
+    pub format: Option<DocumentFormat>,
     pub parameter: VariableDefinitionBlock,
 }
 
+/// Where the config of a `render` comes from
+#[derive(Clone, Debug)]
+pub enum RenderSource {
+    File(String),
+    /// Config embedded in the render statement, relative to the directory of the defining file
+    Inline(Box<GlitterConfig>),
+}
+
+impl Display for RenderSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RenderSource::File(file) => file.fmt(f),
+            RenderSource::Inline(_) => f.write_str("(inline)"),
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct RenderStatement {
-    pub file: String,
+    pub source: RenderSource,
+    pub format: Option<DocumentFormat>,
     pub parameter: VariableDefinitionBlock,
+    /// File (relative to the output root) to write to instead of inlining the result
+    pub output: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ImportStatement {
+    pub file: String,
+    pub format: Option<DocumentFormat>,
+}
+
+#[derive(Clone, Debug)]
+pub struct QuoteStatement {
+    pub file: String,
+    /// Without it the encoding is detected by the byte order mark, falling back to UTF-8
+    pub encoding: Option<TextEncoding>,
 }
 
 #[derive(Clone, Debug)]
 pub struct ExecuteStatement {
-    pub executable: String,
+    pub target: ExecuteTarget,
     pub arguments: ValueDefinitionList,
+    /// Piped into the command
+    pub stdin: Option<Box<ValueDefinition>>,
+    /// Environment variables set for the command
+    pub env: VariableDefinitionBlock,
+    /// Working directory, relative to the directory of the defining file
+    pub cwd: Option<Box<ValueDefinition>>,
+    /// Start the command without inheriting the environment of glitter
+    pub clear_env: bool,
+    pub options: ExecuteOptions,
+    pub structure: ResultStructure,
+}
+
+/// How the output of a command is turned into variables
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResultStructure {
+    /// One text value
+    #[default]
+    Text,
+    /// An object with one entry per line, keyed by the line index: `*{files.0}`
+    Lines,
+    /// Parsed as a document, its content accessible like an `import`
+    Parsed(DocumentFormat),
+}
+
+/// What `execute` starts
+#[derive(Clone, Debug)]
+pub enum ExecuteTarget {
+    /// A binary, relative to the defining file or looked up in `PATH` for bare names
+    Executable(String),
+    /// A command line run by `/bin/sh -c`, the arguments become its positional parameters
+    Shell(String),
+}
+
+impl Display for ExecuteTarget {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExecuteTarget::Executable(executable) => executable.fmt(f),
+            ExecuteTarget::Shell(command_line) => write!(f, "sh -c '{}'", command_line),
+        }
+    }
+}
+
+/// How an executed command is supervised
+#[derive(Clone, Debug, Default)]
+pub struct ExecuteOptions {
+    /// The command gets killed if it runs longer
+    pub timeout: Option<Duration>,
+    /// Use the output even if the command exits with a non-zero code
+    pub allow_failure: bool,
+    pub stderr: StderrHandling,
+    /// Remove leading and trailing whitespace, like the final newline
+    pub trim: bool,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum StderrHandling {
+    /// Collected and only shown as part of the error when the command fails
+    #[default]
+    Capture,
+    /// Passed through to the stderr of glitter
+    Inherit,
+    /// Interleaved with stdout into the result
+    Merge,
+}
+
+/// Line breaks of written text
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Newline {
+    /// As rendered
+    #[default]
+    Preserve,
+    /// `\r\n` becomes `\n`
+    Lf,
+    /// `\n` becomes `\r\n`
+    Crlf,
+}
+
+impl Newline {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().as_str() {
+            "preserve" => Some(Newline::Preserve),
+            "lf" | "unix" => Some(Newline::Lf),
+            "crlf" | "windows" => Some(Newline::Crlf),
+            _ => None,
+        }
+    }
+}
+
+/// Character encoding of quoted and written text. Templates are always UTF-8
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum TextEncoding {
+    #[default]
+    Utf8,
+    Utf16Le,
+    Utf16Be,
+    /// ISO-8859-1, only characters up to U+00FF can be written
+    Latin1,
+}
+
+impl TextEncoding {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Some(TextEncoding::Utf8),
+            "utf-16le" | "utf16le" => Some(TextEncoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(TextEncoding::Utf16Be),
+            "latin1" | "latin-1" | "iso-8859-1" => Some(TextEncoding::Latin1),
+            _ => None,
+        }
+    }
+}
+
+/// How rendered text is written into a file. Options not set are taken from the
+/// enclosing document, then from the command line (or builder)
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct OutputFormat {
+    pub newline: Option<Newline>,
+    /// Start with a byte order mark, never written for Latin-1
+    pub bom: Option<bool>,
+    pub encoding: Option<TextEncoding>,
+}
+
+impl OutputFormat {
+    /// The options set here, the others taken from `fallback`
+    pub fn or(&self, fallback: &OutputFormat) -> OutputFormat {
+        OutputFormat {
+            newline: self.newline.or(fallback.newline),
+            bom: self.bom.or(fallback.bom),
+            encoding: self.encoding.or(fallback.encoding),
+        }
+    }
+}
+
+/// A parameterised template fragment, invoked from templates with arguments
+#[derive(Clone, Debug)]
+pub struct MacroDefinition {
+    /// Names the arguments are bound to, in the order of positional calls
+    pub arguments: Vec<String>,
+    pub template: TemplateValue,
 }
 
 #[derive(Clone, Debug)]
@@ -52,9 +263,10 @@ pub enum ValueDefinition {
     Render(RenderStatement),
     //execute a binary file and return the stdout:
     Execute(ExecuteStatement),
-    //import a YAML file and return the content:
-    Import(String),
-    Quote(String),
+    //import a YAML, JSON or TOML file and return the content:
+    Import(ImportStatement),
+    Quote(QuoteStatement),
+    Macro(MacroDefinition),
     //TODO: Select(Vec<CaseClause>),
 }
 
@@ -67,11 +279,54 @@ pub enum TemplateValue {
     Quote(String),
 }
 
+/// How values written through `*{}` are escaped. Quotes are not added, the value is made
+/// safe to be placed between the quotes the template puts around it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum EscapeMode {
+    /// Written as they are
+    #[default]
+    None,
+    /// `&`, `<`, `>`, `"` and `'` as character references
+    Html,
+    /// `&`, `<`, `>`, `"` and `'` as entities
+    Xml,
+    /// Backslash escapes for `"`, `\` and control characters
+    JsonString,
+    /// `'` becomes `'\''`
+    ShellSingleQuote,
+    /// `"` becomes `""`
+    Csv,
+    /// `'` becomes `''`
+    SqlLiteral,
+}
+
+impl EscapeMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_lowercase().replace('_', "-").as_str() {
+            "none" => Some(EscapeMode::None),
+            "html" => Some(EscapeMode::Html),
+            "xml" => Some(EscapeMode::Xml),
+            "json-string" => Some(EscapeMode::JsonString),
+            "shell-single-quote" => Some(EscapeMode::ShellSingleQuote),
+            "csv" => Some(EscapeMode::Csv),
+            "sql-literal" => Some(EscapeMode::SqlLiteral),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct TemplateDefinition {
     pub header: Option<TemplateValue>,
     pub body: TemplateValue,
     pub footer: Option<TemplateValue>,
+    /// File name pattern, rendered per iteration. Each body is written into its own file
+    pub output_per_iteration: Option<String>,
+    /// Repeat header and footer in every per-iteration file instead of the main output
+    pub repeat_header_footer: bool,
+    /// Of the values written by header, body and footer. Without it the `escape` of the
+    /// config is used
+    pub escape: Option<EscapeMode>,
 }
 
 impl TemplateDefinition {
@@ -80,14 +335,38 @@ impl TemplateDefinition {
             header: None,
             body: TemplateValue::RawValue(template),
             footer: None,
+            output_per_iteration: None,
+            repeat_header_footer: false,
+            escape: None,
         }
     }
 }
 
+/// A named template written into its own file
+#[derive(Clone, Debug)]
+pub struct OutputDefinition {
+    pub name: String,
+    pub file: String,
+    pub template: TemplateDefinition,
+    /// Injections for this template. Without them the injections of the config are used
+    pub injection: Option<Vec<VariableDefinitionBlock>>,
+    /// Falls back to the format of the config
+    pub format: OutputFormat,
+}
+
 #[derive(Clone, Debug)]
 pub struct GlitterConfig {
     pub global: VariableDefinitionBlock,
     pub local: VariableDefinitionBlock,
     pub injection: Vec<VariableDefinitionBlock>,
     pub template: TemplateDefinition,
+    /// File (relative to the output root) to write to instead of the main output
+    pub output: Option<String>,
+    pub outputs: Vec<OutputDefinition>,
+    /// Newlines, encoding and BOM of everything this config writes, unless an output sets its own
+    pub format: OutputFormat,
+    /// Layout file the rendered template is placed into, as `$content`
+    pub extends: Option<String>,
+    /// Named templates placed into the layout, as `$block.<name>`
+    pub blocks: BTreeMap<String, TemplateDefinition>,
 }
@@ -1,14 +1,17 @@
 use std::backtrace::Backtrace;
+use std::collections::BTreeMap;
 use std::convert::TryFrom;
 use std::io::BufRead;
+use std::time::Duration;
 
-use snafu::{ResultExt, Snafu};
-use yaml_rust::yaml::Array;
-use yaml_rust::{yaml::Hash, Yaml, YamlLoader};
+use snafu::{OptionExt, ResultExt, Snafu};
 
+use crate::config::document::{Document, DocumentFormat, DocumentReadError};
 use crate::config::model::{
-    ExecuteStatement, GlitterConfig, LoadStatement, RawValue, RenderStatement, TemplateDefinition,
-    TemplateValue, ValueDefinition, ValueDefinitionList, VariableDefinitionBlock,
+    EscapeMode, ExecuteOptions, ExecuteStatement, ExecuteTarget, GlitterConfig, ImportStatement,
+    LoadStatement, MacroDefinition, Newline, OutputDefinition, OutputFormat, QuoteStatement,
+    RawValue, RenderSource, RenderStatement, ResultStructure, StderrHandling, TemplateDefinition,
+    TemplateValue, TextEncoding, ValueDefinition, ValueDefinitionList, VariableDefinitionBlock,
 };
 
 #[derive(Debug, Snafu)]
@@ -19,12 +22,50 @@ pub enum ConfigReadError {
         source: Box<std::io::Error>,
         backtrace: Backtrace,
     },
-    #[snafu(display("Invalid YAML as input"))]
-    InvalidYaml {
-        #[snafu(source(from(yaml_rust::ScanError, Box::new)))]
-        source: Box<yaml_rust::ScanError>,
+    #[snafu(display("Invalid document as input"))]
+    InvalidDocument {
+        #[snafu(backtrace)]
+        #[snafu(source(from(DocumentReadError, Box::new)))]
+        source: Box<DocumentReadError>,
+    },
+    #[snafu(display("Invalid document no. {} of the stream", index))]
+    InvalidStreamDocument {
+        index: usize,
+        #[snafu(backtrace)]
+        #[snafu(source(from(ConfigReadError, Box::new)))]
+        source: Box<ConfigReadError>,
+    },
+    #[snafu(display("Missing file for output {}", name))]
+    OutputFileMissing { name: String, backtrace: Backtrace },
+    #[snafu(display("Invalid definition for output {}", name))]
+    InvalidOutputDefinition {
+        name: String,
+        #[snafu(backtrace)]
+        #[snafu(source(from(ConfigReadError, Box::new)))]
+        source: Box<ConfigReadError>,
+    },
+    #[snafu(display("Invalid value {} for output option {}", value, option))]
+    InvalidOutputOption {
+        option: &'static str,
+        value: String,
         backtrace: Backtrace,
     },
+    #[snafu(display("Unknown escape mode {}", value))]
+    UnknownEscapeMode { value: String, backtrace: Backtrace },
+    #[snafu(display("Invalid definition for block {}", name))]
+    InvalidBlockDefinition {
+        name: String,
+        #[snafu(backtrace)]
+        #[snafu(source(from(ConfigReadError, Box::new)))]
+        source: Box<ConfigReadError>,
+    },
+    #[snafu(display("Invalid definition for macro {}", name))]
+    InvalidMacro {
+        name: String,
+        #[snafu(backtrace)]
+        #[snafu(source(from(ValueDefinitionError, Box::new)))]
+        source: Box<ValueDefinitionError>,
+    },
     #[snafu(display("Missing injection"))]
     InjectionMissing { backtrace: Backtrace },
     #[snafu(display("Invalid type for injection"))]
@@ -42,7 +83,7 @@ pub enum ConfigReadError {
     },
     #[snafu(display("Empty load source"))]
     MissingLoadSource { backtrace: Backtrace },
-    #[snafu(display("Wrong YAML type for load source:\n{}", yaml_source))]
+    #[snafu(display("Wrong document type for load source:\n{}", yaml_source))]
     InvalidTypeAtLoadSource {
         yaml_source: String,
         backtrace: Backtrace,
@@ -94,6 +135,50 @@ pub enum ValueDefinitionError {
         yaml_source: String,
         backtrace: Backtrace,
     },
+    #[snafu(display("Invalid definition of {} for execute", option))]
+    InvalidExecuteInput {
+        option: &'static str,
+        #[snafu(backtrace)]
+        #[snafu(source(from(ValueDefinitionError, Box::new)))]
+        source: Box<ValueDefinitionError>,
+    },
+    #[snafu(display("Invalid option {} for execute:\n{}", option, yaml_source))]
+    InvalidExecuteOption {
+        option: &'static str,
+        yaml_source: String,
+        #[snafu(backtrace)]
+        source: ExecuteOptionError,
+    },
+    #[snafu(display("Invalid macro definition:\n{}", yaml_source))]
+    InvalidMacroDefinition {
+        yaml_source: String,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Invalid inline config for render"))]
+    InvalidInlineRender {
+        #[snafu(backtrace)]
+        #[snafu(source(from(ConfigReadError, Box::new)))]
+        source: Box<ConfigReadError>,
+    },
+    #[snafu(display("Unknown encoding {} for quote:\n{}", encoding, yaml_source))]
+    UnknownEncoding {
+        encoding: String,
+        yaml_source: String,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Invalid format for value definition:\n{}", yaml_source))]
+    InvalidFormat {
+        yaml_source: String,
+        #[snafu(backtrace)]
+        #[snafu(source(from(DocumentReadError, Box::new)))]
+        source: Box<DocumentReadError>,
+    },
+}
+
+#[derive(Debug, Snafu)]
+pub enum ExecuteOptionError {
+    #[snafu(display("Unsupported value for an execute option"))]
+    UnsupportedOptionValue { backtrace: Backtrace },
 }
 
 #[derive(Debug, Snafu)]
@@ -105,30 +190,26 @@ pub enum TemplateDefinitionError {
     },
 }
 
-impl TryFrom<&Yaml> for RawValue {
+impl TryFrom<&Document> for RawValue {
     type Error = RawValueError;
 
-    fn try_from(value: &Yaml) -> Result<RawValue, Self::Error> {
+    fn try_from(value: &Document) -> Result<RawValue, Self::Error> {
         match value {
-            Yaml::String(string_value) => Ok(RawValue::String(string_value.clone())),
-            Yaml::Boolean(bool_value) => Ok(RawValue::Boolean(*bool_value)),
-            Yaml::Integer(int_value) => Ok(RawValue::Integer(*int_value)),
-            Yaml::Real(real_as_string) => Ok(RawValue::Float(real_as_string.clone())),
-            Yaml::Null => EmptyRawValueSnafu {}.fail(),
+            Document::String(string_value) => Ok(RawValue::String(string_value.clone())),
+            Document::Boolean(bool_value) => Ok(RawValue::Boolean(*bool_value)),
+            Document::Integer(int_value) => Ok(RawValue::Integer(*int_value)),
+            Document::Float(real_as_string) => Ok(RawValue::Float(real_as_string.clone())),
+            Document::Null => EmptyRawValueSnafu {}.fail(),
             _ => UnsupportedRawValueTypeSnafu {}.fail(),
         }
     }
 }
 
-impl TryFrom<&Yaml> for ValueDefinition {
+impl TryFrom<&Document> for ValueDefinition {
     type Error = ValueDefinitionError;
 
-    fn try_from(var_declaration: &Yaml) -> Result<ValueDefinition, Self::Error> {
-        let mut yaml_source = String::new();
-        let mut emitter = yaml_rust::emitter::YamlEmitter::new(&mut yaml_source);
-        if emitter.dump(var_declaration).is_err() {
-            yaml_source = String::from("(INVALID YAML)");
-        }
+    fn try_from(var_declaration: &Document) -> Result<ValueDefinition, Self::Error> {
+        let yaml_source = var_declaration.to_string();
 
         let value_declaration = &var_declaration["value"];
 
@@ -138,19 +219,19 @@ impl TryFrom<&Yaml> for ValueDefinition {
             return Ok(ValueDefinition::Value(raw_value));
         }
 
-        if let Yaml::Hash(var_hash) = &var_declaration["children"] {
+        if let Document::Map(var_hash) = &var_declaration["children"] {
             return Ok(ValueDefinition::Object(
                 ConfigReader::read_var_declarations(var_hash)
                     .context(InvalidSubDefinitionSnafu { var_type: "Object" })?,
             ));
         }
 
-        if let Yaml::String(var_path) = &var_declaration["variable"] {
+        if let Document::String(var_path) = &var_declaration["variable"] {
             return Ok(ValueDefinition::Variable(var_path.clone()));
         }
 
-        if let Yaml::String(file_path) = &var_declaration["load"] {
-            let parameter = if let Yaml::Hash(var_hash) = &var_declaration["parameter"] {
+        if let Document::String(file_path) = &var_declaration["load"] {
+            let parameter = if let Document::Map(var_hash) = &var_declaration["parameter"] {
                 ConfigReader::read_var_declarations(var_hash).context(
                     InvalidSubDefinitionSnafu {
                         var_type: "Load->Parameter",
@@ -162,12 +243,29 @@ impl TryFrom<&Yaml> for ValueDefinition {
 
             return Ok(ValueDefinition::Load(LoadStatement {
                 file: file_path.clone(),
+                format: read_format(var_declaration, &yaml_source)?,
                 parameter,
             }));
         }
 
-        if let Yaml::String(file_path) = &var_declaration["render"] {
-            let parameter = if let Yaml::Hash(var_hash) = &var_declaration["parameter"] {
+        if !var_declaration["macro"].is_null() && !var_declaration["macro"].is_badvalue() {
+            return Ok(ValueDefinition::Macro(read_macro(
+                &var_declaration["macro"],
+                &var_declaration["arguments"],
+                &yaml_source,
+            )?));
+        }
+
+        let render_source = match &var_declaration["render"] {
+            Document::String(file_path) => Some(RenderSource::File(file_path.clone())),
+            inline @ Document::Map(_) => Some(RenderSource::Inline(Box::new(
+                ConfigReader::read_config(inline).context(InvalidInlineRenderSnafu)?,
+            ))),
+            _ => None,
+        };
+
+        if let Some(source) = render_source {
+            let parameter = if let Document::Map(var_hash) = &var_declaration["parameter"] {
                 ConfigReader::read_var_declarations(var_hash).context(
                     InvalidSubDefinitionSnafu {
                         var_type: "Render->Parameter",
@@ -178,13 +276,23 @@ impl TryFrom<&Yaml> for ValueDefinition {
             };
 
             return Ok(ValueDefinition::Render(RenderStatement {
-                file: file_path.clone(),
+                source,
+                format: read_format(var_declaration, &yaml_source)?,
                 parameter,
+                output: var_declaration["output"].as_str().map(str::to_owned),
             }));
         }
 
-        if let Yaml::String(executable) = &var_declaration["execute"] {
-            let arguments = if let Yaml::Array(value_list) = &var_declaration["arguments"] {
+        let execute_target = match (&var_declaration["execute"], &var_declaration["shell"]) {
+            (Document::String(executable), _) => {
+                Some(ExecuteTarget::Executable(executable.clone()))
+            }
+            (_, Document::String(command_line)) => Some(ExecuteTarget::Shell(command_line.clone())),
+            _ => None,
+        };
+
+        if let Some(target) = execute_target {
+            let arguments = if let Document::Array(value_list) = &var_declaration["arguments"] {
                 ConfigReader::read_value_list(value_list).context(InvalidSubDefinitionSnafu {
                     var_type: "Execute->Arguments",
                 })?
@@ -192,18 +300,66 @@ impl TryFrom<&Yaml> for ValueDefinition {
                 ValueDefinitionList::new()
             };
 
+            let stdin = match &var_declaration["stdin"] {
+                Document::Null | Document::BadValue => None,
+                stdin => Some(Box::new(
+                    ValueDefinition::try_from(stdin)
+                        .context(InvalidExecuteInputSnafu { option: "stdin" })?,
+                )),
+            };
+
+            let env = if let Document::Map(var_hash) = &var_declaration["env"] {
+                ConfigReader::read_var_declarations(var_hash).context(
+                    InvalidSubDefinitionSnafu {
+                        var_type: "Execute->Env",
+                    },
+                )?
+            } else {
+                VariableDefinitionBlock::new()
+            };
+
+            let cwd = match &var_declaration["cwd"] {
+                Document::Null | Document::BadValue => None,
+                cwd => Some(Box::new(
+                    ValueDefinition::try_from(cwd)
+                        .context(InvalidExecuteInputSnafu { option: "cwd" })?,
+                )),
+            };
+
             return Ok(ValueDefinition::Execute(ExecuteStatement {
-                executable: executable.clone(),
+                target,
                 arguments,
+                stdin,
+                env,
+                cwd,
+                clear_env: var_declaration["clear_env"] == Document::Boolean(true),
+                options: read_execute_options(var_declaration, &yaml_source)?,
+                structure: read_result_structure(var_declaration, &yaml_source)?,
             }));
         }
 
-        if let Yaml::String(file_path) = &var_declaration["quote"] {
-            return Ok(ValueDefinition::Quote(file_path.clone()));
+        if let Document::String(file_path) = &var_declaration["quote"] {
+            let encoding = match &var_declaration["encoding"] {
+                Document::String(encoding) => Some(TextEncoding::from_name(encoding).context(
+                    UnknownEncodingSnafu {
+                        encoding,
+                        yaml_source,
+                    },
+                )?),
+                _ => None,
+            };
+
+            return Ok(ValueDefinition::Quote(QuoteStatement {
+                file: file_path.clone(),
+                encoding,
+            }));
         }
 
-        if let Yaml::String(file_path) = &var_declaration["import"] {
-            return Ok(ValueDefinition::Import(file_path.clone()));
+        if let Document::String(file_path) = &var_declaration["import"] {
+            return Ok(ValueDefinition::Import(ImportStatement {
+                file: file_path.clone(),
+                format: read_format(var_declaration, &yaml_source)?,
+            }));
         }
 
         // TODO: Select & Execute
@@ -212,88 +368,521 @@ impl TryFrom<&Yaml> for ValueDefinition {
     }
 }
 
-impl TryFrom<&Yaml> for TemplateValue {
+impl TryFrom<&Document> for TemplateValue {
     type Error = TemplateDefinitionError;
 
-    fn try_from(value: &Yaml) -> Result<TemplateValue, Self::Error> {
-        if let Yaml::String(raw_value) = &value["value"] {
+    fn try_from(value: &Document) -> Result<TemplateValue, Self::Error> {
+        if let Document::String(raw_value) = &value["value"] {
             return Ok(TemplateValue::RawValue(raw_value.clone()));
         }
 
-        if let Yaml::String(file_path) = &value["quote"] {
+        if let Document::String(file_path) = &value["quote"] {
             return Ok(TemplateValue::Quote(file_path.clone()));
         }
 
-        let mut yaml_source = String::new();
-        let mut emitter = yaml_rust::emitter::YamlEmitter::new(&mut yaml_source);
-        if emitter.dump(value).is_err() {
-            yaml_source = String::from("(INVALID YAML)");
+        InvalidTemplateSubstructureSnafu {
+            yaml_source: value.to_string(),
+        }
+        .fail()
+    }
+}
+
+/// The template may be given as string or as `value`/`quote`, `arguments` as list of names
+fn read_macro(
+    template: &Document,
+    arguments: &Document,
+    yaml_source: &str,
+) -> Result<MacroDefinition, ValueDefinitionError> {
+    let template = match template {
+        Document::String(raw_value) => TemplateValue::RawValue(raw_value.clone()),
+        template => TemplateValue::try_from(template)
+            .ok()
+            .context(InvalidMacroDefinitionSnafu { yaml_source })?,
+    };
+
+    let arguments = match arguments {
+        Document::Array(names) => names
+            .iter()
+            .map(|name| name.as_str().map(str::to_owned))
+            .collect::<Option<Vec<_>>>()
+            .context(InvalidMacroDefinitionSnafu { yaml_source })?,
+        Document::Null | Document::BadValue => vec![],
+        _ => return InvalidMacroDefinitionSnafu { yaml_source }.fail(),
+    };
+
+    Ok(MacroDefinition {
+        arguments,
+        template,
+    })
+}
+
+fn read_format(
+    var_declaration: &Document,
+    yaml_source: &str,
+) -> Result<Option<DocumentFormat>, ValueDefinitionError> {
+    match &var_declaration["format"] {
+        Document::String(format_name) => Ok(Some(
+            DocumentFormat::from_name(format_name).context(InvalidFormatSnafu { yaml_source })?,
+        )),
+        _ => Ok(None),
+    }
+}
+
+fn read_execute_options(
+    var_declaration: &Document,
+    yaml_source: &str,
+) -> Result<ExecuteOptions, ValueDefinitionError> {
+    let timeout = match &var_declaration["timeout"] {
+        Document::Null | Document::BadValue => None,
+        timeout => Some(read_duration(timeout).context(InvalidExecuteOptionSnafu {
+            option: "timeout",
+            yaml_source,
+        })?),
+    };
+
+    let allow_failure = match &var_declaration["allow_failure"] {
+        Document::Boolean(allow_failure) => *allow_failure,
+        Document::Null | Document::BadValue => false,
+        _ => {
+            return UnsupportedOptionValueSnafu {}
+                .fail()
+                .context(InvalidExecuteOptionSnafu {
+                    option: "allow_failure",
+                    yaml_source,
+                })
         }
+    };
+
+    let stderr = match &var_declaration["stderr"] {
+        Document::Null | Document::BadValue => StderrHandling::default(),
+        Document::String(handling) if handling == "capture" => StderrHandling::Capture,
+        Document::String(handling) if handling == "inherit" => StderrHandling::Inherit,
+        Document::String(handling) if handling == "merge" => StderrHandling::Merge,
+        _ => {
+            return UnsupportedOptionValueSnafu {}
+                .fail()
+                .context(InvalidExecuteOptionSnafu {
+                    option: "stderr",
+                    yaml_source,
+                })
+        }
+    };
+
+    Ok(ExecuteOptions {
+        timeout,
+        allow_failure,
+        stderr,
+        trim: var_declaration["trim"] == Document::Boolean(true),
+    })
+}
 
-        InvalidTemplateSubstructureSnafu { yaml_source }.fail()
+fn read_result_structure(
+    var_declaration: &Document,
+    yaml_source: &str,
+) -> Result<ResultStructure, ValueDefinitionError> {
+    let split_lines = var_declaration["split_lines"] == Document::Boolean(true);
+
+    match (&var_declaration["parse"], split_lines) {
+        (Document::String(format_name), false) => Ok(ResultStructure::Parsed(
+            DocumentFormat::from_name(format_name).context(InvalidFormatSnafu { yaml_source })?,
+        )),
+        (Document::Null | Document::BadValue, true) => Ok(ResultStructure::Lines),
+        (Document::Null | Document::BadValue, false) => Ok(ResultStructure::Text),
+        _ => UnsupportedOptionValueSnafu {}
+            .fail()
+            .context(InvalidExecuteOptionSnafu {
+                option: "parse",
+                yaml_source,
+            }),
     }
 }
 
-pub struct ConfigReader {}
+/// Durations are either a number of seconds or a string like `500ms`, `10s`, `2m` or `1h`
+fn read_duration(duration: &Document) -> Result<Duration, ExecuteOptionError> {
+    let (amount, unit) = match duration {
+        Document::Integer(seconds) => (seconds.to_string(), "s"),
+        Document::Float(seconds) => (seconds.clone(), "s"),
+        Document::String(duration) => {
+            let duration = duration.trim();
+            let split_at = duration
+                .find(|c: char| !c.is_ascii_digit() && c != '.')
+                .unwrap_or(duration.len());
+            let (amount, unit) = duration.split_at(split_at);
+            (
+                amount.to_owned(),
+                if unit.is_empty() { "s" } else { unit.trim() },
+            )
+        }
+        _ => return UnsupportedOptionValueSnafu {}.fail(),
+    };
+
+    let factor = match unit {
+        "ms" => 0.001,
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        _ => return UnsupportedOptionValueSnafu {}.fail(),
+    };
+    let amount = amount
+        .parse::<f64>()
+        .ok()
+        .filter(|amount| amount.is_finite() && *amount >= 0.0);
+
+    match amount {
+        Some(amount) => Ok(Duration::from_secs_f64(amount * factor)),
+        None => UnsupportedOptionValueSnafu {}.fail(),
+    }
+}
+
+pub struct ConfigReader {
+    format: DocumentFormat,
+}
 
 impl ConfigReader {
     pub fn new() -> ConfigReader {
-        ConfigReader {}
+        Self::for_format(DocumentFormat::Yaml)
+    }
+
+    pub fn for_format(format: DocumentFormat) -> ConfigReader {
+        ConfigReader { format }
     }
 
     pub fn read<T: BufRead>(&self, input: &mut T) -> Result<GlitterConfig, ConfigReadError> {
         let mut buffer = String::new();
         input.read_to_string(&mut buffer).context(InputIoSnafu)?;
 
-        let yaml_stream = YamlLoader::load_from_str(&buffer).context(InvalidYamlSnafu)?;
-        let yaml_content = &yaml_stream[0];
+        let yaml_content = Document::parse(&buffer, self.format).context(InvalidDocumentSnafu)?;
+        Self::read_config(&yaml_content)
+    }
 
+    /// Reads every document of a (YAML) stream as its own config
+    pub fn read_stream<T: BufRead>(
+        &self,
+        input: &mut T,
+    ) -> Result<Vec<GlitterConfig>, ConfigReadError> {
+        let mut buffer = String::new();
+        input.read_to_string(&mut buffer).context(InputIoSnafu)?;
+
+        let mut yaml_stream =
+            Document::parse_stream(&buffer, self.format).context(InvalidDocumentSnafu)?;
+        match yaml_stream.len() {
+            0 => Ok(vec![Self::read_config(&Document::Null)?]),
+            1 => Ok(vec![Self::read_config(&yaml_stream.remove(0))?]),
+            _ => yaml_stream
+                .iter()
+                .enumerate()
+                .map(|(index, yaml_content)| {
+                    Self::read_config(yaml_content)
+                        .context(InvalidStreamDocumentSnafu { index: index + 1 })
+                })
+                .collect(),
+        }
+    }
+
+    fn read_config(yaml_content: &Document) -> Result<GlitterConfig, ConfigReadError> {
         let global: VariableDefinitionBlock =
-            if let Yaml::Hash(global_hash) = &yaml_content["global"] {
+            if let Document::Map(global_hash) = &yaml_content["global"] {
                 Self::read_var_declarations(global_hash)
                     .context(InvalidVarDefinitionBlockSnafu { block: "global" })?
             } else {
                 VariableDefinitionBlock::new()
             };
 
-        let local: VariableDefinitionBlock = if let Yaml::Hash(local_hash) = &yaml_content["local"]
-        {
-            Self::read_var_declarations(local_hash)
-                .context(InvalidVarDefinitionBlockSnafu { block: "local" })?
-        } else {
-            VariableDefinitionBlock::new()
-        };
+        let local: VariableDefinitionBlock =
+            if let Document::Map(local_hash) = &yaml_content["local"] {
+                Self::read_var_declarations(local_hash)
+                    .context(InvalidVarDefinitionBlockSnafu { block: "local" })?
+            } else {
+                VariableDefinitionBlock::new()
+            };
 
         let injection: Vec<VariableDefinitionBlock> = match &yaml_content["injection"] {
-            Yaml::Array(array) => Self::read_injections(array)?,
-            Yaml::Null => return InjectionMissingSnafu {}.fail(),
+            Document::Array(array) => Self::read_injections(array)?,
+            Document::Null | Document::BadValue => return InjectionMissingSnafu {}.fail(),
             _ => return InvalidTypeAtInjectionSnafu {}.fail(),
         };
 
-        let template = match &yaml_content["template"] {
-            Yaml::String(simple_template) => {
-                TemplateDefinition::simple_template(simple_template.to_owned())
-            }
-            Yaml::Hash(_) => Self::read_hbf_template(&yaml_content["template"])?,
-            Yaml::Null => return TemplateMissingSnafu {}.fail(),
-            _ => InvalidTypeAtTemplateSnafu {}.fail()?,
+        let template = Self::read_template(&yaml_content["template"])?;
+
+        let output = yaml_content["output"].as_str().map(str::to_owned);
+
+        let outputs = if let Document::Map(outputs_hash) = &yaml_content["outputs"] {
+            Self::read_outputs(outputs_hash)?
+        } else {
+            vec![]
+        };
+
+        let format = Self::read_output_format(yaml_content)?;
+
+        let mut local = local;
+        if let Document::Map(macros_hash) = &yaml_content["macros"] {
+            local.extend(Self::read_macros(macros_hash)?);
+        }
+
+        let extends = yaml_content["extends"].as_str().map(str::to_owned);
+
+        let blocks = if let Document::Map(blocks_hash) = &yaml_content["blocks"] {
+            Self::read_blocks(blocks_hash)?
+        } else {
+            BTreeMap::new()
         };
 
-        Ok(GlitterConfig {
+        let escape = Self::read_escape(yaml_content)?;
+        let mut config = GlitterConfig {
             global,
             local,
             injection,
             template,
+            output,
+            outputs,
+            format,
+            extends,
+            blocks,
+        };
+        if let Some(escape) = escape {
+            Self::inherit_escape(&mut config, escape);
+        }
+
+        Ok(config)
+    }
+
+    /// The escape of a config applies to all its templates not setting their own, including
+    /// those of `render` configs written inline
+    fn inherit_escape(config: &mut GlitterConfig, escape: EscapeMode) {
+        for template in std::iter::once(&mut config.template)
+            .chain(config.outputs.iter_mut().map(|output| &mut output.template))
+            .chain(config.blocks.values_mut())
+        {
+            template.escape = template.escape.or(Some(escape));
+        }
+
+        let definitions = config
+            .global
+            .values_mut()
+            .chain(config.local.values_mut())
+            .chain(
+                config
+                    .injection
+                    .iter_mut()
+                    .flat_map(|block| block.values_mut()),
+            );
+        for definition in definitions {
+            Self::inherit_escape_inline(definition, escape);
+        }
+    }
+
+    fn inherit_escape_inline(definition: &mut ValueDefinition, escape: EscapeMode) {
+        match definition {
+            ValueDefinition::Object(block) => {
+                for definition in block.values_mut() {
+                    Self::inherit_escape_inline(definition, escape);
+                }
+            }
+            ValueDefinition::Render(render) => {
+                if let RenderSource::Inline(config) = &mut render.source {
+                    Self::inherit_escape(config, escape);
+                }
+                for definition in render.parameter.values_mut() {
+                    Self::inherit_escape_inline(definition, escape);
+                }
+            }
+            ValueDefinition::Load(load) => {
+                for definition in load.parameter.values_mut() {
+                    Self::inherit_escape_inline(definition, escape);
+                }
+            }
+            ValueDefinition::Execute(execute) => {
+                let definitions = execute
+                    .arguments
+                    .iter_mut()
+                    .chain(execute.env.values_mut())
+                    .chain(execute.stdin.as_deref_mut())
+                    .chain(execute.cwd.as_deref_mut());
+                for definition in definitions {
+                    Self::inherit_escape_inline(definition, escape);
+                }
+            }
+            ValueDefinition::Value(_)
+            | ValueDefinition::Variable(_)
+            | ValueDefinition::Import(_)
+            | ValueDefinition::Quote(_)
+            | ValueDefinition::Macro(_) => (),
+        }
+    }
+
+    fn read_template(template: &Document) -> Result<TemplateDefinition, ConfigReadError> {
+        match template {
+            Document::String(simple_template) => Ok(TemplateDefinition::simple_template(
+                simple_template.to_owned(),
+            )),
+            Document::Map(_) => Self::read_hbf_template(template),
+            Document::Null | Document::BadValue => TemplateMissingSnafu {}.fail(),
+            _ => InvalidTypeAtTemplateSnafu {}.fail(),
+        }
+    }
+
+    fn read_macros(
+        macros: &[(Document, Document)],
+    ) -> Result<VariableDefinitionBlock, ConfigReadError> {
+        let mut macro_block = VariableDefinitionBlock::new();
+
+        for (name, definition) in macros {
+            let name = if let Document::String(name) = name {
+                name.clone()
+            } else {
+                return InvalidTypeAsVarNameSnafu {
+                    key: String::from("🤷"),
+                }
+                .fail();
+            };
+
+            let template = match definition {
+                Document::String(_) => definition,
+                _ => &definition["template"],
+            };
+            let macro_definition =
+                read_macro(template, &definition["arguments"], &definition.to_string())
+                    .context(InvalidMacroSnafu { name: name.clone() })?;
+            macro_block.insert(name, ValueDefinition::Macro(macro_definition));
+        }
+
+        Ok(macro_block)
+    }
+
+    fn read_blocks(
+        blocks: &[(Document, Document)],
+    ) -> Result<BTreeMap<String, TemplateDefinition>, ConfigReadError> {
+        let mut block_map = BTreeMap::new();
+
+        for (name, block) in blocks {
+            let name = if let Document::String(name) = name {
+                name.clone()
+            } else {
+                return InvalidTypeAsVarNameSnafu {
+                    key: String::from("🤷"),
+                }
+                .fail();
+            };
+
+            let template = Self::read_template(block)
+                .context(InvalidBlockDefinitionSnafu { name: name.clone() })?;
+            block_map.insert(name, template);
+        }
+
+        Ok(block_map)
+    }
+
+    fn read_outputs(
+        outputs: &[(Document, Document)],
+    ) -> Result<Vec<OutputDefinition>, ConfigReadError> {
+        let mut output_list = vec![];
+
+        for (name, output) in outputs {
+            let name = if let Document::String(name) = name {
+                name.clone()
+            } else {
+                return InvalidTypeAsVarNameSnafu {
+                    key: String::from("🤷"),
+                }
+                .fail();
+            };
+
+            let file = if let Document::String(file) = &output["file"] {
+                file.clone()
+            } else {
+                return OutputFileMissingSnafu { name }.fail();
+            };
+
+            let mut template = Self::read_template(&output["template"])
+                .context(InvalidOutputDefinitionSnafu { name: name.clone() })?;
+            template.escape = template.escape.or(Self::read_escape(output)
+                .context(InvalidOutputDefinitionSnafu { name: name.clone() })?);
+
+            let injection = match &output["injection"] {
+                Document::Array(array) => Some(
+                    Self::read_injections(array)
+                        .context(InvalidOutputDefinitionSnafu { name: name.clone() })?,
+                ),
+                _ => None,
+            };
+
+            let format = Self::read_output_format(output)
+                .context(InvalidOutputDefinitionSnafu { name: name.clone() })?;
+
+            output_list.push(OutputDefinition {
+                name,
+                file,
+                template,
+                injection,
+                format,
+            });
+        }
+
+        Ok(output_list)
+    }
+
+    /// The `newline`, `encoding` and `bom` keys next to an output
+    fn read_output_format(definition: &Document) -> Result<OutputFormat, ConfigReadError> {
+        let newline = match &definition["newline"] {
+            Document::Null | Document::BadValue => None,
+            Document::String(name) => {
+                Some(Newline::from_name(name).context(InvalidOutputOptionSnafu {
+                    option: "newline",
+                    value: name,
+                })?)
+            }
+            other => {
+                return InvalidOutputOptionSnafu {
+                    option: "newline",
+                    value: other.to_string(),
+                }
+                .fail()
+            }
+        };
+
+        let encoding = match &definition["encoding"] {
+            Document::Null | Document::BadValue => None,
+            Document::String(name) => Some(TextEncoding::from_name(name).context(
+                InvalidOutputOptionSnafu {
+                    option: "encoding",
+                    value: name,
+                },
+            )?),
+            other => {
+                return InvalidOutputOptionSnafu {
+                    option: "encoding",
+                    value: other.to_string(),
+                }
+                .fail()
+            }
+        };
+
+        let bom = match &definition["bom"] {
+            Document::Null | Document::BadValue => None,
+            Document::Boolean(bom) => Some(*bom),
+            other => {
+                return InvalidOutputOptionSnafu {
+                    option: "bom",
+                    value: other.to_string(),
+                }
+                .fail()
+            }
+        };
+
+        Ok(OutputFormat {
+            newline,
+            bom,
+            encoding,
         })
     }
 
     fn read_injections(
-        injections: &[Yaml],
+        injections: &[Document],
     ) -> Result<Vec<VariableDefinitionBlock>, ConfigReadError> {
         let mut variable_block_list = Vec::<VariableDefinitionBlock>::new();
 
         for single_injection in injections.iter() {
-            if let Yaml::Hash(hash) = single_injection {
+            if let Document::Map(hash) = single_injection {
                 variable_block_list.push(
                     Self::read_var_declarations(hash)
                         .context(InvalidVarDefinitionBlockSnafu { block: "injection" })?,
@@ -307,7 +896,7 @@ impl ConfigReader {
     }
 
     pub(crate) fn read_value_list(
-        value_declaration_list: &Array,
+        value_declaration_list: &[Document],
     ) -> Result<ValueDefinitionList, ConfigReadError> {
         let mut index = -1;
         let mut value_list = vec![];
@@ -326,12 +915,12 @@ impl ConfigReader {
     }
 
     pub(crate) fn read_var_declarations(
-        var_declaration_block: &Hash,
+        var_declaration_block: &[(Document, Document)],
     ) -> Result<VariableDefinitionBlock, ConfigReadError> {
         let mut variable_block = VariableDefinitionBlock::new();
 
-        for hash_key in var_declaration_block.keys() {
-            let key_as_string = if let Yaml::String(string_value) = hash_key {
+        for (hash_key, hash_value) in var_declaration_block {
+            let key_as_string = if let Document::String(string_value) = hash_key {
                 string_value.clone()
             } else {
                 return InvalidTypeAsVarNameSnafu {
@@ -340,8 +929,8 @@ impl ConfigReader {
                 .fail();
             };
 
-            let var_declaration = ValueDefinition::try_from(&var_declaration_block[hash_key])
-                .context(InvalidValueDefinitionSnafu {
+            let var_declaration =
+                ValueDefinition::try_from(hash_value).context(InvalidValueDefinitionSnafu {
                     key: key_as_string.clone(),
                 })?;
             variable_block.insert(key_as_string, var_declaration);
@@ -350,8 +939,8 @@ impl ConfigReader {
         Ok(variable_block)
     }
 
-    fn read_hbf_template(template: &Yaml) -> Result<TemplateDefinition, ConfigReadError> {
-        let header = if template["header"].is_null() {
+    fn read_hbf_template(template: &Document) -> Result<TemplateDefinition, ConfigReadError> {
+        let header = if template["header"].is_null() || template["header"].is_badvalue() {
             None
         } else {
             Some(
@@ -360,7 +949,7 @@ impl ConfigReader {
             )
         };
 
-        let footer = if template["footer"].is_null() {
+        let footer = if template["footer"].is_null() || template["footer"].is_badvalue() {
             None
         } else {
             Some(
@@ -372,13 +961,32 @@ impl ConfigReader {
         let body = TemplateValue::try_from(&template["body"])
             .context(InvalidTemplateVarDefinitionSnafu { section: "body" })?;
 
+        let output_per_iteration = template["output_per_iteration"].as_str().map(str::to_owned);
+        let repeat_header_footer = template["repeat_header_footer"] == Document::Boolean(true);
+
         Ok(TemplateDefinition {
             header,
             body,
             footer,
+            output_per_iteration,
+            repeat_header_footer,
+            escape: Self::read_escape(template)?,
         })
     }
 
+    fn read_escape(definition: &Document) -> Result<Option<EscapeMode>, ConfigReadError> {
+        match &definition["escape"] {
+            Document::Null | Document::BadValue => Ok(None),
+            Document::String(name) => Ok(Some(
+                EscapeMode::from_name(name).context(UnknownEscapeModeSnafu { value: name })?,
+            )),
+            other => UnknownEscapeModeSnafu {
+                value: other.to_string(),
+            }
+            .fail(),
+        }
+    }
+
     pub fn load<T: BufRead>(
         &self,
         input: &mut T,
@@ -386,16 +994,15 @@ impl ConfigReader {
         let mut buffer = String::new();
         input.read_to_string(&mut buffer).context(InputIoSnafu)?;
 
-        let yaml_stream = YamlLoader::load_from_str(&buffer).context(InvalidYamlSnafu)?;
-        let yaml_content = &yaml_stream[0];
+        let yaml_content = Document::parse(&buffer, self.format).context(InvalidDocumentSnafu)?;
 
         match yaml_content {
-            Yaml::Hash(hash) => Ok(Self::read_var_declarations(hash).context(
+            Document::Map(hash) => Ok(Self::read_var_declarations(&hash).context(
                 InvalidVarDefinitionBlockSnafu {
                     block: "Load Source",
                 },
             )?),
-            Yaml::Null => MissingLoadSourceSnafu {}.fail(),
+            Document::Null => MissingLoadSourceSnafu {}.fail(),
             _ => InvalidTypeAtLoadSourceSnafu {
                 yaml_source: buffer,
             }
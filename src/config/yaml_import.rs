@@ -2,8 +2,8 @@ use std::backtrace::Backtrace;
 use std::io::BufRead;
 
 use snafu::{ResultExt, Snafu};
-use yaml_rust::{yaml::Hash, Yaml, YamlLoader};
 
+use crate::config::document::{Document, DocumentFormat, DocumentReadError};
 use crate::config::model::{RawValue, ValueDefinition, VariableDefinitionBlock};
 
 #[derive(Debug, Snafu)]
@@ -14,13 +14,13 @@ pub enum YamlImportReadError {
         source: Box<std::io::Error>,
         backtrace: Backtrace,
     },
-    #[snafu(display("Input is not valid YAML"))]
-    YamlError {
-        #[snafu(source(from(yaml_rust::ScanError, Box::new)))]
-        source: Box<yaml_rust::ScanError>,
-        backtrace: Backtrace,
+    #[snafu(display("Input is not a valid document"))]
+    DocumentError {
+        #[snafu(backtrace)]
+        #[snafu(source(from(DocumentReadError, Box::new)))]
+        source: Box<DocumentReadError>,
     },
-    #[snafu(display("Incorrect YAML structure"))]
+    #[snafu(display("Incorrect document structure"))]
     IncorrectYaml { backtrace: Backtrace },
     #[snafu(display("Unsupported Input Type {}", value_type))]
     InvalidType {
@@ -29,11 +29,13 @@ pub enum YamlImportReadError {
     },
 }
 
-pub(crate) struct YamlImporter {}
+pub(crate) struct YamlImporter {
+    format: DocumentFormat,
+}
 
 impl YamlImporter {
-    pub fn new() -> Self {
-        YamlImporter {}
+    pub fn for_format(format: DocumentFormat) -> Self {
+        YamlImporter { format }
     }
 
     pub fn read<T: BufRead>(
@@ -43,67 +45,68 @@ impl YamlImporter {
         let mut buffer = String::new();
         input.read_to_string(&mut buffer).context(FileIoSnafu)?;
 
-        let yaml_stream = YamlLoader::load_from_str(&buffer).context(YamlSnafu)?;
-        if let Yaml::Hash(yaml_content) = &yaml_stream[0] {
-            Self::convert_hash(yaml_content)
-        } else {
-            IncorrectYamlSnafu {}.fail()
+        self.read_str(&buffer)
+    }
+
+    /// Reads a whole document, which must either be a mapping or a list
+    pub fn read_str(&self, content: &str) -> Result<VariableDefinitionBlock, YamlImportReadError> {
+        match Document::parse(content, self.format).context(DocumentSnafu)? {
+            Document::Map(yaml_content) => Self::convert_hash(&yaml_content),
+            Document::Array(yaml_content) => Self::convert_array(&yaml_content),
+            _ => IncorrectYamlSnafu {}.fail(),
         }
     }
 
-    pub fn convert_hash(hash: &Hash) -> Result<VariableDefinitionBlock, YamlImportReadError> {
+    pub fn convert_hash(
+        hash: &[(Document, Document)],
+    ) -> Result<VariableDefinitionBlock, YamlImportReadError> {
         let mut result = VariableDefinitionBlock::new();
 
-        for hash_key in hash.keys() {
+        for (hash_key, hash_value) in hash {
             let key_string = hash_key.as_str().unwrap().to_owned();
+            result.insert(key_string, Self::convert_value(hash_value)?);
+        }
+        Ok(result)
+    }
+
+    /// Lists become objects keyed by the index of each entry: `*{list.0}`
+    pub fn convert_array(
+        array: &[Document],
+    ) -> Result<VariableDefinitionBlock, YamlImportReadError> {
+        let mut result = VariableDefinitionBlock::new();
 
-            match &hash[hash_key] {
-                Yaml::Hash(sub_hash) => result.insert(
-                    key_string,
-                    ValueDefinition::Object(Self::convert_hash(sub_hash)?),
-                ),
-                Yaml::Integer(int_value) => result.insert(
-                    key_string,
-                    ValueDefinition::Value(RawValue::Integer(*int_value)),
-                ),
-                Yaml::String(string_value) => result.insert(
-                    key_string,
-                    ValueDefinition::Value(RawValue::String(string_value.clone())),
-                ),
-                Yaml::Boolean(bool_value) => result.insert(
-                    key_string,
-                    ValueDefinition::Value(RawValue::Boolean(*bool_value)),
-                ),
-                Yaml::Real(real_as_string) => result.insert(
-                    key_string,
-                    ValueDefinition::Value(RawValue::Float(real_as_string.clone())),
-                ),
-                Yaml::Array(_) => {
-                    return InvalidTypeSnafu {
-                        value_type: String::from("Array"),
-                    }
-                    .fail();
-                }
-                Yaml::Alias(_) => {
-                    return InvalidTypeSnafu {
-                        value_type: String::from("Alias"),
-                    }
-                    .fail();
-                }
-                Yaml::Null => {
-                    return InvalidTypeSnafu {
-                        value_type: String::from("Null"),
-                    }
-                    .fail();
-                }
-                Yaml::BadValue => {
-                    return InvalidTypeSnafu {
-                        value_type: String::from("BadValue"),
-                    }
-                    .fail();
-                }
-            };
+        for (index, value) in array.iter().enumerate() {
+            result.insert(index.to_string(), Self::convert_value(value)?);
         }
         Ok(result)
     }
+
+    fn convert_value(value: &Document) -> Result<ValueDefinition, YamlImportReadError> {
+        match value {
+            Document::Map(sub_hash) => Ok(ValueDefinition::Object(Self::convert_hash(sub_hash)?)),
+            Document::Array(sub_array) => {
+                Ok(ValueDefinition::Object(Self::convert_array(sub_array)?))
+            }
+            Document::Integer(int_value) => {
+                Ok(ValueDefinition::Value(RawValue::Integer(*int_value)))
+            }
+            Document::String(string_value) => Ok(ValueDefinition::Value(RawValue::String(
+                string_value.clone(),
+            ))),
+            Document::Boolean(bool_value) => {
+                Ok(ValueDefinition::Value(RawValue::Boolean(*bool_value)))
+            }
+            Document::Float(real_as_string) => Ok(ValueDefinition::Value(RawValue::Float(
+                real_as_string.clone(),
+            ))),
+            Document::Null => InvalidTypeSnafu {
+                value_type: String::from("Null"),
+            }
+            .fail(),
+            Document::BadValue => InvalidTypeSnafu {
+                value_type: String::from("BadValue"),
+            }
+            .fail(),
+        }
+    }
 }
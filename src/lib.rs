@@ -1,13 +1,24 @@
 extern crate yaml_rust;
 
-use std::io::{BufRead, Write};
+use std::io::{BufRead, ErrorKind, Write};
+use std::sync::Arc;
+use std::time::SystemTime;
 
 use snafu::{ResultExt, Snafu};
 
+use crate::config::document::DocumentFormat;
+use crate::config::model::OutputFormat;
 use crate::config::reader::ConfigReader;
-use crate::processing::GlitterProcessor;
-use crate::rendering::template_rendering::TemplateRenderer;
+use crate::processing::{AccessPolicy, DiskFileSystem, DumpFormat, GlitterProcessor, VariableDump};
+use crate::rendering::output_files::{DirectoryOutput, OutputFiles};
+use crate::rendering::template_rendering::{
+    TemplateRenderer, TemplateRendererConfig, DEFAULT_RENDERER_CONFIG,
+};
+use crate::rendering::text_encoding::EncodedWriter;
 
+pub use crate::builder::{Glitter, GlitterBuilder};
+
+mod builder;
 pub mod config;
 pub mod processing;
 pub mod rendering;
@@ -24,27 +35,224 @@ pub enum GlitterError {
         #[snafu(backtrace)]
         source: crate::rendering::TemplateRenderError,
     },
+    #[snafu(display("Failed to resolve variables."))]
+    VariableDumpFailed {
+        #[snafu(backtrace)]
+        source: crate::rendering::ValueRenderError,
+    },
+    #[snafu(display("Failed to write output file {}.", path))]
+    OutputFileFailed {
+        path: String,
+        #[snafu(source(from(std::io::Error, Box::new)))]
+        source: Box<std::io::Error>,
+        backtrace: std::backtrace::Backtrace,
+    },
+    #[snafu(display("Failed to write output."))]
+    OutputWriteFailed {
+        #[snafu(source(from(std::io::Error, Box::new)))]
+        source: Box<std::io::Error>,
+        backtrace: std::backtrace::Backtrace,
+    },
+    #[snafu(display("Failed to read config file {}.", path))]
+    ConfigFileUnreadable {
+        path: String,
+        #[snafu(source(from(std::io::Error, Box::new)))]
+        source: Box<std::io::Error>,
+        backtrace: std::backtrace::Backtrace,
+    },
+    #[snafu(display("No config given to render."))]
+    ConfigSourceMissing {
+        backtrace: std::backtrace::Backtrace,
+    },
+    #[cfg(feature = "tokio")]
+    #[snafu(display("Rendering task did not finish."))]
+    RenderTaskFailed {
+        source: tokio::task::JoinError,
+        backtrace: std::backtrace::Backtrace,
+    },
+    #[snafu(display("Rendered text is not valid UTF-8."))]
+    RenderedTextInvalid {
+        source: std::string::FromUtf8Error,
+        backtrace: std::backtrace::Backtrace,
+    },
 }
 
+/// How `process_into` renders. By default with unrestricted access, one job and the output
+/// written as rendered.
+#[derive(Clone, Debug, Default)]
+pub struct ProcessOptions {
+    /// Files are only read and commands only executed as far as it allows
+    pub policy: AccessPolicy,
+    /// With more than one, `render`, `execute` and `load` values are evaluated concurrently,
+    /// the output stays the same
+    pub jobs: usize,
+    /// Newlines, encoding and BOM of the main output and of all files whose config doesn't
+    /// set its own
+    pub output_format: OutputFormat,
+    /// The time of `$now` and `$today`, by default `SOURCE_DATE_EPOCH` or the current time
+    pub now: Option<SystemTime>,
+}
+
+/// Renders into `output` only: configs writing additional files fail, `process_into` takes
+/// the place they are written to.
 pub fn process<TInput: BufRead, TOutput: Write>(
     input: &mut TInput,
     inputname: String,
     starting_directory: String,
     output: &mut TOutput,
 ) -> Result<(), GlitterError> {
-    let config_reader = ConfigReader::new();
-    let config = config_reader.read(input).context(InvalidConfigSnafu)?;
+    process_into(
+        input,
+        inputname,
+        starting_directory,
+        output,
+        &mut None::<DirectoryOutput>,
+        ProcessOptions::default(),
+    )
+}
+
+/// Like `process`, but all additional files (documents with an `output` key, `outputs`,
+/// sub-renders with an `output`) are created through `output_files`.
+/// Every document of a YAML stream is processed, all other output is concatenated.
+pub fn process_into<TInput: BufRead, TOutput: Write>(
+    input: &mut TInput,
+    inputname: String,
+    starting_directory: String,
+    output: &mut TOutput,
+    output_files: &mut dyn OutputFiles,
+    options: ProcessOptions,
+) -> Result<(), GlitterError> {
+    let config_reader = ConfigReader::for_format(DocumentFormat::resolve(None, &inputname));
+    let configs = config_reader
+        .read_stream(input)
+        .context(InvalidConfigSnafu)?;
+
+    render_processors(
+        GlitterProcessor::stream(
+            inputname,
+            starting_directory,
+            configs,
+            options.policy,
+            Arc::new(DiskFileSystem),
+            options.now,
+        ),
+        output,
+        output_files,
+        &DEFAULT_RENDERER_CONFIG,
+        options.jobs,
+        &options.output_format,
+    )
+}
+
+/// Each document is written in its own format, options it doesn't set are taken from
+/// `output_format`. Only the first document written to the main output starts with a BOM.
+fn render_processors(
+    processors: Vec<GlitterProcessor>,
+    output: &mut dyn Write,
+    output_files: &mut dyn OutputFiles,
+    renderer_config: &TemplateRendererConfig,
+    jobs: usize,
+    output_format: &OutputFormat,
+) -> Result<(), GlitterError> {
+    let mut main_output_started = false;
+
+    for processor in processors {
+        let format = processor.output_format().or(output_format);
+
+        if let Some(output_file) = processor.output_file() {
+            let file = processor
+                .policy()
+                .check_output_path(output_file)
+                .map_err(|violation| std::io::Error::new(ErrorKind::PermissionDenied, violation))
+                .and_then(|()| output_files.create(output_file))
+                .context(OutputFileFailedSnafu { path: output_file })?;
+            let mut file_output = EncodedWriter::new(file, &format);
+            processor
+                .render(
+                    &mut TemplateRenderer::based_upon_config(
+                        renderer_config.clone(),
+                        &mut file_output,
+                    )
+                    .with_output_files(output_files)
+                    .with_jobs(jobs)
+                    .with_output_format(*output_format),
+                )
+                .context(RenderingFailedSnafu)?;
+            file_output
+                .flush()
+                .context(OutputFileFailedSnafu { path: output_file })?;
+        } else {
+            let format = OutputFormat {
+                bom: format.bom.filter(|_| !main_output_started),
+                ..format
+            };
+            main_output_started = true;
+
+            let mut main_output = EncodedWriter::new(&mut *output, &format);
+            processor
+                .render(
+                    &mut TemplateRenderer::based_upon_config(
+                        renderer_config.clone(),
+                        &mut main_output,
+                    )
+                    .with_output_files(output_files)
+                    .with_jobs(jobs)
+                    .with_output_format(*output_format),
+                )
+                .context(RenderingFailedSnafu)?;
+            main_output.flush().context(OutputWriteFailedSnafu)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// How `dump_variables` evaluates the variables. By default with unrestricted access and
+/// instructions shown as placeholders.
+#[derive(Clone, Debug, Default)]
+pub struct DumpOptions {
+    /// Evaluate `execute` and `render` instead of showing placeholders
+    pub evaluate_instructions: bool,
+    /// Files are only read and commands only executed as far as it allows
+    pub policy: AccessPolicy,
+    /// The time of `$now` and `$today`, by default `SOURCE_DATE_EPOCH` or the current time
+    pub now: Option<SystemTime>,
+}
+
+/// Writes the variables of every document of a stream
+pub fn dump_variables<TInput: BufRead, TOutput: Write>(
+    input: &mut TInput,
+    inputname: String,
+    starting_directory: String,
+    output: &mut TOutput,
+    format: DumpFormat,
+    options: DumpOptions,
+) -> Result<(), GlitterError> {
+    let config_reader = ConfigReader::for_format(DocumentFormat::resolve(None, &inputname));
+    let configs = config_reader
+        .read_stream(input)
+        .context(InvalidConfigSnafu)?;
 
-    let processor = GlitterProcessor::new(inputname, starting_directory, config);
-    processor
-        .run(TemplateRenderer::new(output))
-        .context(RenderingFailedSnafu)?;
+    let dumps = GlitterProcessor::stream(
+        inputname,
+        starting_directory,
+        configs,
+        options.policy,
+        Arc::new(DiskFileSystem),
+        options.now,
+    )
+    .iter()
+    .map(|processor| processor.dump_variables(options.evaluate_instructions))
+    .collect::<Result<Vec<_>, _>>()
+    .context(VariableDumpFailedSnafu)?;
+    VariableDump::write_stream(&dumps, format, output).context(OutputWriteFailedSnafu)?;
 
     Ok(())
 }
 
-pub fn report<E: 'static>(err: &E)
+pub fn report<E>(err: &E)
 where
+    E: 'static,
     E: std::error::Error,
     E: snafu::ErrorCompat,
     E: Send + Sync,
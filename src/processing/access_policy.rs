@@ -0,0 +1,236 @@
+use std::backtrace::Backtrace;
+use std::path::{Component, Path, PathBuf};
+
+use snafu::Snafu;
+
+use crate::rendering::var_rendering::{is_bare_name, is_executable};
+
+#[derive(Debug, Snafu)]
+pub enum AccessViolation {
+    #[snafu(display("Executing commands is disabled, refusing to run {}", executable))]
+    ExecutionDisabled {
+        executable: String,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Executable {} is not on the allow-list", executable))]
+    ExecutableNotAllowed {
+        executable: String,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Absolute path {} is not allowed, paths must be relative", path))]
+    AbsolutePath { path: String, backtrace: Backtrace },
+    #[snafu(display("Output file {} is outside of the output root", path))]
+    OutputOutsideOfRoot { path: String, backtrace: Backtrace },
+    #[snafu(display("Path {} is outside of the root directory {}", path, root.display()))]
+    OutsideOfRoot {
+        path: String,
+        root: PathBuf,
+        backtrace: Backtrace,
+    },
+}
+
+/// Restricts what a glitter file may execute, which files it may read and where it may write.
+/// The default allows everything.
+#[derive(Clone, Debug)]
+pub struct AccessPolicy {
+    /// Whether `execute` and `shell` may run at all
+    pub allow_exec: bool,
+    /// Executables which may be run, by path or by name looked up in glitter's `PATH`.
+    /// `None` allows all
+    pub allowed_executables: Option<Vec<String>>,
+    /// Directory all read files (`quote`, `load`, `import`, `render`, template files) must be
+    /// inside. Output files must stay within the output root then.
+    pub root: Option<PathBuf>,
+}
+
+impl Default for AccessPolicy {
+    fn default() -> Self {
+        AccessPolicy {
+            allow_exec: true,
+            allowed_executables: None,
+            root: None,
+        }
+    }
+}
+
+impl AccessPolicy {
+    /// Checks the path as written in a glitter file, once resolved against that file's directory
+    pub(crate) fn check_path(&self, written: &str, resolved: &Path) -> Result<(), AccessViolation> {
+        let Some(root) = &self.root else {
+            return Ok(());
+        };
+
+        if Path::new(written).has_root() {
+            return AbsolutePathSnafu { path: written }.fail();
+        }
+
+        if !is_inside(resolved, root) {
+            return OutsideOfRootSnafu {
+                path: written,
+                root: root.clone(),
+            }
+            .fail();
+        }
+
+        Ok(())
+    }
+
+    /// Checks the path of an output file, relative to the output root
+    pub(crate) fn check_output_path(&self, path: &str) -> Result<(), AccessViolation> {
+        if self.root.is_none() {
+            return Ok(());
+        }
+
+        if Path::new(path).has_root() {
+            return AbsolutePathSnafu { path }.fail();
+        }
+        if Path::new(path)
+            .components()
+            .any(|component| component == Component::ParentDir)
+        {
+            return OutputOutsideOfRootSnafu { path }.fail();
+        }
+
+        Ok(())
+    }
+
+    /// Checks the executable as found on disk, `written` is only named in messages
+    pub(crate) fn check_executable(
+        &self,
+        written: &str,
+        program: &Path,
+    ) -> Result<(), AccessViolation> {
+        if !self.allow_exec {
+            return ExecutionDisabledSnafu {
+                executable: written,
+            }
+            .fail();
+        }
+
+        match &self.allowed_executables {
+            Some(allowed)
+                if !allowed
+                    .iter()
+                    .filter_map(|entry| allowed_program(entry))
+                    .any(|entry| canonical(&entry) == canonical(program)) =>
+            {
+                ExecutableNotAllowedSnafu {
+                    executable: written,
+                }
+                .fail()
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The program an allow-list entry stands for. Bare names are looked up in glitter's own
+/// `PATH`, so a file of the same name next to a glitter file is not allowed with them.
+fn allowed_program(entry: &str) -> Option<PathBuf> {
+    if !is_bare_name(entry) {
+        return Some(PathBuf::from(entry));
+    }
+
+    std::env::split_paths(&std::env::var_os("PATH")?)
+        .map(|directory| directory.join(entry))
+        .find(|candidate| is_executable(candidate))
+}
+
+/// Compares without following `..` blindly: symlinks are resolved for existing paths,
+/// everything else is normalized lexically
+fn is_inside(path: &Path, root: &Path) -> bool {
+    let root = canonical(root);
+    canonical(path).starts_with(root)
+}
+
+pub(crate) fn canonical(path: &Path) -> PathBuf {
+    if let Ok(canonical) = path.canonicalize() {
+        return canonical;
+    }
+
+    let absolute = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut normalized = PathBuf::new();
+    for component in absolute.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::CurDir => (),
+            other => normalized.push(other),
+        }
+    }
+
+    // the not yet existing part can't contain symlinks, but its parents might
+    match (normalized.parent(), normalized.file_name()) {
+        (Some(parent), Some(name)) if parent != normalized => canonical(parent).join(name),
+        _ => normalized,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rooted(root: &Path) -> AccessPolicy {
+        AccessPolicy {
+            root: Some(root.to_path_buf()),
+            ..AccessPolicy::default()
+        }
+    }
+
+    #[test]
+    fn paths_stay_inside_the_root() {
+        let root = std::env::temp_dir().join("glitter-policy-root");
+        let policy = rooted(&root);
+        let directory = root.join("sub");
+
+        for written in ["data.yaml", "../data.yaml", "./a/../b.txt"] {
+            let resolved = directory.join(written);
+            assert!(policy.check_path(written, &resolved).is_ok(), "{}", written);
+        }
+        for written in ["../../evil.sh", "../../glitter-policy-root-other/x"] {
+            let resolved = directory.join(written);
+            assert!(
+                matches!(
+                    policy.check_path(written, &resolved),
+                    Err(AccessViolation::OutsideOfRoot { .. })
+                ),
+                "{}",
+                written
+            );
+        }
+    }
+
+    #[test]
+    fn absolute_paths_are_refused_even_under_the_root() {
+        let root = std::env::temp_dir().join("glitter-policy-root");
+        let written = root.join("data.yaml");
+        let written = written.to_str().unwrap();
+
+        assert!(matches!(
+            rooted(&root).check_path(written, Path::new(written)),
+            Err(AccessViolation::AbsolutePath { .. })
+        ));
+        assert!(AccessPolicy::default()
+            .check_path(written, Path::new(written))
+            .is_ok());
+    }
+
+    #[test]
+    fn output_paths_stay_within_the_output_root() {
+        let policy = rooted(&std::env::temp_dir());
+
+        assert!(policy.check_output_path("out/a.txt").is_ok());
+        assert!(matches!(
+            policy.check_output_path("out/../../a.txt"),
+            Err(AccessViolation::OutputOutsideOfRoot { .. })
+        ));
+        assert!(matches!(
+            policy.check_output_path("/etc/passwd"),
+            Err(AccessViolation::AbsolutePath { .. })
+        ));
+        assert!(AccessPolicy::default()
+            .check_output_path("../a.txt")
+            .is_ok());
+    }
+}
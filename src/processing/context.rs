@@ -1,15 +1,23 @@
 use crate::config::model::{
     RawValue, TemplateDefinition, ValueDefinition, VariableDefinitionBlock,
 };
+use crate::processing::access_policy::{AccessPolicy, AccessViolation};
+use crate::processing::file_system::FileSystem;
 use crate::processing::var_store::VariableStore;
+use std::io::BufRead;
 use std::path::Path;
-use std::rc::Rc;
+use std::sync::Arc;
+
+/// Built-in variables of the global store, overridden by any definition of the same name
+pub(crate) const CLOCK_VARIABLES: [&str; 2] = ["$now", "$today"];
 
 pub(crate) struct ProcessingContext {
     pub(crate) directory: String,
-    pub(crate) local: Rc<VariableStore>,
-    pub(crate) injection: Vec<Rc<VariableStore>>,
+    pub(crate) local: Arc<VariableStore>,
+    pub(crate) injection: Vec<Arc<VariableStore>>,
     pub(crate) template: Option<TemplateDefinition>,
+    pub(crate) policy: Arc<AccessPolicy>,
+    pub(crate) file_system: Arc<dyn FileSystem>,
 }
 
 impl ProcessingContext {
@@ -19,14 +27,18 @@ impl ProcessingContext {
         local_source: VariableDefinitionBlock,
         injection_source: Vec<VariableDefinitionBlock>,
         template_source: TemplateDefinition,
+        policy: Arc<AccessPolicy>,
+        file_system: Arc<dyn FileSystem>,
     ) -> Self {
         let injection = Self::build_injection_store(injection_source, &filename, &directory);
 
         ProcessingContext {
             directory,
-            local: Rc::new(VariableStore::from(local_source)),
+            local: Arc::new(VariableStore::from(local_source)),
             injection,
             template: Some(template_source),
+            policy,
+            file_system,
         }
     }
 
@@ -34,12 +46,23 @@ impl ProcessingContext {
         injection_source: Vec<VariableDefinitionBlock>,
         filename: &str,
         directory: &str,
-    ) -> Vec<Rc<VariableStore>> {
+    ) -> Vec<Arc<VariableStore>> {
+        // templates only see injections, so the first one refers to the clock variables
+        // unless an injection defines them itself
+        let clock_references = CLOCK_VARIABLES
+            .into_iter()
+            .filter(|key| !injection_source.iter().any(|i| i.contains_key(*key)))
+            .map(|key| (key.to_owned(), ValueDefinition::Variable(key.to_owned())))
+            .collect::<Vec<_>>();
+
         let mut iteration_count = 0;
         injection_source
             .into_iter()
-            .map(|i| {
+            .map(|mut i| {
                 iteration_count += 1;
+                if iteration_count == 1 {
+                    i.extend(clock_references.iter().cloned());
+                }
 
                 let store = VariableStore::from(i)
                     .insert(
@@ -55,7 +78,7 @@ impl ProcessingContext {
                         ValueDefinition::Value(RawValue::String(directory.into())),
                     );
 
-                Rc::new(store)
+                Arc::new(store)
             })
             .collect::<Vec<_>>()
     }
@@ -67,31 +90,72 @@ impl ProcessingContext {
         injection_source: Vec<VariableDefinitionBlock>,
         template_source: TemplateDefinition,
         parameter_source: VariableDefinitionBlock,
-        source_context: Rc<ProcessingContext>,
+        source_context: Arc<ProcessingContext>,
     ) -> Self {
         let injection = Self::build_injection_store(injection_source, &filename, &directory);
+        let policy = Arc::clone(&source_context.policy);
+        let file_system = Arc::clone(&source_context.file_system);
 
         ProcessingContext {
             directory,
-            local: Rc::new(VariableStore::with_context(
+            local: Arc::new(VariableStore::with_context(
                 local_source,
                 parameter_source,
                 source_context,
             )),
             injection,
             template: Some(template_source),
+            policy,
+            file_system,
+        }
+    }
+
+    /// Shares the local variables of this context, but renders another template with own injections
+    pub(crate) fn sibling(
+        &self,
+        filename: &str,
+        injection_source: Vec<VariableDefinitionBlock>,
+        template_source: TemplateDefinition,
+    ) -> Self {
+        let injection = Self::build_injection_store(injection_source, filename, &self.directory);
+
+        ProcessingContext {
+            directory: self.directory.clone(),
+            local: Arc::clone(&self.local),
+            injection,
+            template: Some(template_source),
+            policy: Arc::clone(&self.policy),
+            file_system: Arc::clone(&self.file_system),
+        }
+    }
+
+    /// Shares the local variables of this context, with the arguments of a macro as only injection
+    pub(crate) fn macro_scope(&self, filename: &str, arguments: VariableDefinitionBlock) -> Self {
+        let injection = Self::build_injection_store(vec![arguments], filename, &self.directory);
+
+        ProcessingContext {
+            directory: self.directory.clone(),
+            local: Arc::clone(&self.local),
+            injection,
+            template: None,
+            policy: Arc::clone(&self.policy),
+            file_system: Arc::clone(&self.file_system),
         }
     }
 
     pub(crate) fn local_standalone(
         directory: String,
         local_source: VariableDefinitionBlock,
+        policy: Arc<AccessPolicy>,
+        file_system: Arc<dyn FileSystem>,
     ) -> Self {
         ProcessingContext {
             directory,
-            local: Rc::new(VariableStore::from(local_source)),
+            local: Arc::new(VariableStore::from(local_source)),
             injection: vec![],
             template: None,
+            policy,
+            file_system,
         }
     }
 
@@ -99,21 +163,40 @@ impl ProcessingContext {
         directory: String,
         local_source: VariableDefinitionBlock,
         parameter_source: VariableDefinitionBlock,
-        source_context: Rc<ProcessingContext>,
+        source_context: Arc<ProcessingContext>,
     ) -> Self {
+        let policy = Arc::clone(&source_context.policy);
+        let file_system = Arc::clone(&source_context.file_system);
+
         ProcessingContext {
             directory,
-            local: Rc::new(VariableStore::with_context(
+            local: Arc::new(VariableStore::with_context(
                 local_source,
                 parameter_source,
                 source_context,
             )),
             injection: vec![],
             template: None,
+            policy,
+            file_system,
         }
     }
 
-    pub(crate) fn resolve_filename(&self, filename: &str) -> String {
+    /// The path of a file to read, relative to this context's directory, checked against the policy
+    pub(crate) fn resolve_filename(&self, filename: &str) -> Result<String, AccessViolation> {
+        let full_path = self.join_path(filename);
+        self.policy.check_path(filename, Path::new(&full_path))?;
+
+        Ok(full_path)
+    }
+
+    /// Opens a file already resolved through `resolve_filename`
+    pub(crate) fn open_file(&self, full_path: &str) -> std::io::Result<Box<dyn BufRead>> {
+        self.file_system.open(Path::new(full_path))
+    }
+
+    /// The path relative to this context's directory, without any policy check
+    pub(crate) fn join_path(&self, filename: &str) -> String {
         Path::new(&self.directory)
             .join(filename)
             .to_str()
@@ -0,0 +1,94 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Cursor, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+
+/// Source of every file glitter reads: glitter files for `render`, `load` and `import`
+/// sources, `quote`s and template files. Paths are already resolved against the
+/// directory of the reading glitter file. Shared between threads rendering the same config.
+pub trait FileSystem: Send + Sync {
+    fn open(&self, path: &Path) -> std::io::Result<Box<dyn BufRead>>;
+}
+
+/// Reads from the disk
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DiskFileSystem;
+
+impl FileSystem for DiskFileSystem {
+    fn open(&self, path: &Path) -> std::io::Result<Box<dyn BufRead>> {
+        Ok(Box::new(BufReader::new(File::open(path)?)))
+    }
+}
+
+/// Files kept in memory, e.g. loaded from a database or embedded into the binary.
+/// Paths are compared after removing `.` and resolving `..`, so `./a/../b.txt` is `b.txt`.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryFileSystem {
+    files: BTreeMap<PathBuf, Vec<u8>>,
+}
+
+impl MemoryFileSystem {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_file<P: AsRef<Path>, C: Into<Vec<u8>>>(mut self, path: P, contents: C) -> Self {
+        self.insert(path, contents);
+        self
+    }
+
+    pub fn insert<P: AsRef<Path>, C: Into<Vec<u8>>>(&mut self, path: P, contents: C) {
+        self.files.insert(normalize(path.as_ref()), contents.into());
+    }
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn open(&self, path: &Path) -> std::io::Result<Box<dyn BufRead>> {
+        match self.files.get(&normalize(path)) {
+            Some(contents) => Ok(Box::new(Cursor::new(contents.clone()))),
+            None => Err(std::io::Error::new(
+                ErrorKind::NotFound,
+                format!("{} does not exist in memory", path.display()),
+            )),
+        }
+    }
+}
+
+/// Reads from `upper` first, files missing there are read from `lower`
+pub struct OverlayFileSystem {
+    upper: Box<dyn FileSystem>,
+    lower: Box<dyn FileSystem>,
+}
+
+impl OverlayFileSystem {
+    pub fn new<U: FileSystem + 'static, L: FileSystem + 'static>(upper: U, lower: L) -> Self {
+        OverlayFileSystem {
+            upper: Box::new(upper),
+            lower: Box::new(lower),
+        }
+    }
+}
+
+impl FileSystem for OverlayFileSystem {
+    fn open(&self, path: &Path) -> std::io::Result<Box<dyn BufRead>> {
+        match self.upper.open(path) {
+            Err(error) if error.kind() == ErrorKind::NotFound => self.lower.open(path),
+            result => result,
+        }
+    }
+}
+
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir if normalized.file_name().is_some() => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+
+    normalized
+}
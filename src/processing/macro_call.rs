@@ -0,0 +1,192 @@
+use crate::config::model::RawValue;
+
+/// A macro invocation inside a processing block, either `call name(a, b)` or `name x=a y=b`
+pub(crate) struct MacroCall {
+    pub(crate) name: String,
+    pub(crate) arguments: MacroArguments,
+}
+
+pub(crate) enum MacroArguments {
+    Positional(Vec<MacroArgument>),
+    Named(Vec<(String, MacroArgument)>),
+}
+
+/// An argument as written in the call
+pub(crate) enum MacroArgument {
+    Literal(RawValue),
+    /// Path of a variable, resolved like any other variable of the calling template
+    Variable(String),
+}
+
+impl MacroCall {
+    /// `None` for plain variable paths, `Some(Err(..))` for calls which can't be parsed.
+    /// Without the `call ` prefix a block is only a call if all words after the name are
+    /// `key=value`, so variable names containing whitespace are still looked up.
+    pub(crate) fn parse(block: &str) -> Option<Result<MacroCall, String>> {
+        if let Some(call) = block.strip_prefix("call ") {
+            return Some(Self::parse_positional(call.trim()).ok_or_else(|| block.to_owned()));
+        }
+
+        let (name, arguments) = block.split_once(char::is_whitespace)?;
+        Self::parse_named(name, arguments).map(Ok)
+    }
+
+    fn parse_positional(call: &str) -> Option<MacroCall> {
+        let (name, arguments) = call.strip_suffix(')')?.split_once('(')?;
+        let name = name.trim();
+        if name.is_empty() {
+            return None;
+        }
+
+        let arguments = if arguments.trim().is_empty() {
+            vec![]
+        } else {
+            split_outside_quotes(arguments, |c| c == ',')?
+                .iter()
+                .map(|argument| MacroArgument::parse(argument.trim()))
+                .collect::<Option<Vec<_>>>()?
+        };
+
+        Some(MacroCall {
+            name: name.to_owned(),
+            arguments: MacroArguments::Positional(arguments),
+        })
+    }
+
+    fn parse_named(name: &str, arguments: &str) -> Option<MacroCall> {
+        let arguments = split_outside_quotes(arguments, char::is_whitespace)?
+            .iter()
+            .filter(|argument| !argument.is_empty())
+            .map(|argument| {
+                let (key, value) = argument.split_once('=')?;
+                if key.is_empty() {
+                    return None;
+                }
+                Some((key.to_owned(), MacroArgument::parse(value)?))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if name.is_empty() || arguments.is_empty() {
+            return None;
+        }
+
+        Some(MacroCall {
+            name: name.to_owned(),
+            arguments: MacroArguments::Named(arguments),
+        })
+    }
+}
+
+impl MacroArgument {
+    fn parse(argument: &str) -> Option<MacroArgument> {
+        if argument.is_empty() {
+            return None;
+        }
+
+        for quote in ['"', '\''] {
+            if let Some(text) = argument
+                .strip_prefix(quote)
+                .and_then(|rest| rest.strip_suffix(quote))
+            {
+                return Some(MacroArgument::Literal(RawValue::String(text.to_owned())));
+            }
+        }
+
+        let literal = match argument {
+            "true" => RawValue::Boolean(true),
+            "false" => RawValue::Boolean(false),
+            number if number.parse::<i64>().is_ok() => RawValue::Integer(number.parse().ok()?),
+            number if number.parse::<f64>().is_ok() => RawValue::Float(number.to_owned()),
+            path => return Some(MacroArgument::Variable(path.to_owned())),
+        };
+
+        Some(MacroArgument::Literal(literal))
+    }
+}
+
+/// Splits at separators which are not enclosed in single or double quotes,
+/// `None` if a quote is left open
+fn split_outside_quotes(text: &str, is_separator: impl Fn(char) -> bool) -> Option<Vec<&str>> {
+    let mut parts = vec![];
+    let mut open_quote = None;
+    let mut start = 0;
+
+    for (index, c) in text.char_indices() {
+        match open_quote {
+            Some(quote) if c == quote => open_quote = None,
+            Some(_) => (),
+            None if c == '"' || c == '\'' => open_quote = Some(c),
+            None if is_separator(c) => {
+                parts.push(&text[start..index]);
+                start = index + c.len_utf8();
+            }
+            None => (),
+        }
+    }
+
+    if open_quote.is_some() {
+        return None;
+    }
+    parts.push(&text[start..]);
+
+    Some(parts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(block: &str) -> MacroCall {
+        match MacroCall::parse(block) {
+            Some(Ok(call)) => call,
+            _ => panic!("`{}` is no macro call", block),
+        }
+    }
+
+    #[test]
+    fn positional_arguments_split_outside_quotes() {
+        let call = parse("call box(\"a, b\", 3, 1.50, true, title)");
+        assert_eq!(call.name, "box");
+        let MacroArguments::Positional(arguments) = call.arguments else {
+            panic!("expected positional arguments");
+        };
+        assert!(matches!(
+            arguments.as_slice(),
+            [
+                MacroArgument::Literal(RawValue::String(text)),
+                MacroArgument::Literal(RawValue::Integer(3)),
+                MacroArgument::Literal(RawValue::Float(float)),
+                MacroArgument::Literal(RawValue::Boolean(true)),
+                MacroArgument::Variable(path),
+            ] if text == "a, b" && float == "1.50" && path == "title"
+        ));
+    }
+
+    #[test]
+    fn named_arguments_need_key_value_pairs() {
+        let call = parse("box title='x y'  width=3");
+        let MacroArguments::Named(arguments) = call.arguments else {
+            panic!("expected named arguments");
+        };
+        assert!(matches!(
+            arguments.as_slice(),
+            [
+                (title, MacroArgument::Literal(RawValue::String(text))),
+                (width, MacroArgument::Literal(RawValue::Integer(3))),
+            ] if title == "title" && text == "x y" && width == "width"
+        ));
+    }
+
+    #[test]
+    fn variable_paths_are_no_calls() {
+        for block in ["name", "my var", "box x=1 y", "box x=\"open", "box =1"] {
+            assert!(MacroCall::parse(block).is_none(), "{}", block);
+        }
+    }
+
+    #[test]
+    fn broken_calls_are_errors() {
+        for block in ["call box(\"a)", "call box", "call (a)", "call box(a,,b)"] {
+            assert!(matches!(MacroCall::parse(block), Some(Err(_))), "{}", block);
+        }
+    }
+}
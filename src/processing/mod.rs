@@ -2,12 +2,21 @@ use std::ops::Index;
 
 use crate::config::model::VariableDefinitionBlock;
 
+mod access_policy;
 mod context;
+mod file_system;
+mod macro_call;
 mod processor;
+mod var_dump;
 mod var_store;
 
-pub(crate) use crate::processing::context::ProcessingContext;
+pub use crate::processing::access_policy::{AccessPolicy, AccessViolation};
+pub(crate) use crate::processing::context::{ProcessingContext, CLOCK_VARIABLES};
+pub use crate::processing::file_system::{
+    DiskFileSystem, FileSystem, MemoryFileSystem, OverlayFileSystem,
+};
 pub use crate::processing::processor::GlitterProcessor;
+pub use crate::processing::var_dump::{DumpFormat, ResolvedBlock, ResolvedValue, VariableDump};
 
 #[derive(Clone, Debug)]
 pub(crate) struct ValuePath(Vec<String>);
@@ -1,31 +1,72 @@
 use crate::{
-    config::model::{GlitterConfig, ValueDefinition, VariableDefinitionBlock},
+    config::document::DocumentFormat,
+    config::model::{
+        GlitterConfig, MacroDefinition, OutputDefinition, OutputFormat, RawValue, RenderSource,
+        ResultStructure, TemplateDefinition, TemplateValue, ValueDefinition,
+        VariableDefinitionBlock,
+    },
     config::yaml_import::YamlImporter,
+    processing::macro_call::{MacroArgument, MacroArguments, MacroCall},
     processing::var_store::{ProcessingInstruction, StoredVariable, VariableStore},
-    processing::{ProcessingContext, ValuePath},
+    processing::{
+        access_policy::canonical, AccessPolicy, DiskFileSystem, FileSystem, ProcessingContext,
+        ResolvedBlock, ResolvedValue, ValuePath, VariableDump, CLOCK_VARIABLES,
+    },
+    rendering::value_format::clock_values,
     rendering::var_rendering::{
-        RenderableExecutionResult, RenderableQuote, RenderableRawValue, RenderableVariable,
-        SubRender,
+        ExecuteEnvironment, MacroExpansion, RenderableExecutionResult, RenderableQuote,
+        RenderableRawValue, RenderableVariable, SubRender,
     },
     rendering::{
-        FailedAccessingFilesystemSnafu, FailedProcessingVariableSnafu, FailedReadingTextSnafu,
-        FailedResolvingVariableSnafu, InvalidSubRenderConfigSnafu, LoadCommandFailedSnafu,
-        TemplateRenderError, ValueRenderError, ValueRenderer,
+        AccessDeniedSnafu, ExecuteResultUnparsableSnafu, FailedAccessingFilesystemSnafu,
+        FailedProcessingVariableSnafu, FailedReadingTextSnafu, FailedResolvingVariableSnafu,
+        ImportCommandFailedSnafu, InvalidLayoutContentSnafu, InvalidMacroCallSnafu,
+        InvalidSubRenderConfigSnafu, LayoutCycleSnafu, LayoutFailedSnafu, LoadCommandFailedSnafu,
+        MacroArgumentCountSnafu, MissingMacroArgumentSnafu, NotAMacroSnafu, OutputFileSnafu,
+        SubRenderWithOutputsSnafu, TemplateRenderError, UnknownMacroArgumentSnafu,
+        ValueRenderError, ValueRenderer,
     },
     ConfigReader, TemplateRenderer,
 };
 use snafu::ResultExt;
-use std::fs::File;
-use std::io::BufReader;
-use std::path::Path;
-use std::rc::Rc;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// The `$filename` of configs embedded in a `render`
+const INLINE_RENDER_FILENAME: &str = "(inline)";
+
+/// The `$filename` within the body of a macro
+const MACRO_FILENAME: &str = "(macro)";
 
 enum NextVarProcessingInstruction {
-    ContinueElsewhere(Option<Rc<ProcessingContext>>, ValuePath, RequestSource),
+    ContinueElsewhere(Option<Arc<ProcessingContext>>, ValuePath, RequestSource),
     ReturnValue(Box<dyn RenderableVariable>),
+    ReturnObject(Vec<String>),
+    ReturnMacro(MacroDefinition, Arc<ProcessingContext>),
     ReportMissing,
 }
 
+/// The outcome of following a variable path until it can't be followed any further
+enum ResolvedNode {
+    Value(Box<dyn RenderableVariable>),
+    Object {
+        keys: Vec<String>,
+        resolution_path: Vec<String>,
+    },
+    Unevaluated {
+        description: String,
+        resolution_path: Vec<String>,
+    },
+    /// A macro along with the context it was defined in, to be bound to arguments
+    Macro {
+        definition: MacroDefinition,
+        context: Arc<ProcessingContext>,
+    },
+}
+
 #[derive(Clone, PartialEq)]
 enum RequestSource {
     /// The variable is accessed from a template of same context
@@ -45,48 +86,269 @@ enum RequestSource {
 }
 
 pub struct GlitterProcessor {
-    global: Rc<ProcessingContext>,
-    root: Rc<ProcessingContext>,
+    global: Arc<ProcessingContext>,
+    root: Arc<ProcessingContext>,
+    output: Option<String>,
+    outputs: Vec<RoutedOutput>,
+    layout: Option<Layout>,
+    format: OutputFormat,
+}
+
+/// A named template of the `outputs` section, sharing the local variables of its processor
+struct RoutedOutput {
+    file: String,
+    format: OutputFormat,
+    processor: GlitterProcessor,
+}
+
+/// Defines `$now` (RFC 3339) and `$today` (`YYYY-MM-DD`) unless the globals do. Without
+/// a given time, `SOURCE_DATE_EPOCH` keeps builds reproducible.
+fn define_clock(global: &mut VariableDefinitionBlock, now: Option<SystemTime>) {
+    let now = now
+        .or_else(|| {
+            let seconds = std::env::var("SOURCE_DATE_EPOCH").ok()?;
+            let seconds = seconds.trim().parse::<u64>().ok()?;
+            Some(SystemTime::UNIX_EPOCH + Duration::from_secs(seconds))
+        })
+        .unwrap_or_else(SystemTime::now);
+
+    let (now, today) = clock_values(now);
+    for (key, value) in CLOCK_VARIABLES.into_iter().zip([now, today]) {
+        global
+            .entry(key.to_owned())
+            .or_insert(ValueDefinition::Value(RawValue::String(value)));
+    }
+}
+
+// processors are moved to and shared between worker threads
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<GlitterProcessor>();
+};
+
+/// The layout file a config `extends`, filled with its rendered body and `blocks`
+struct Layout {
+    file: String,
+    blocks: Vec<(String, GlitterProcessor)>,
+    /// Layout files the content is placed into already, a repeat would never end
+    extended: Vec<PathBuf>,
 }
 
 impl GlitterProcessor {
     pub fn new(filename: String, directory: String, config: GlitterConfig) -> Self {
-        let global = Rc::new(ProcessingContext::local_standalone(
+        Self::with_policy(filename, directory, config, AccessPolicy::default())
+    }
+
+    /// A processor only reading and executing what the policy allows
+    pub fn with_policy(
+        filename: String,
+        directory: String,
+        mut config: GlitterConfig,
+        policy: AccessPolicy,
+    ) -> Self {
+        let mut global = std::mem::take(&mut config.global);
+        define_clock(&mut global, None);
+        let global = Arc::new(ProcessingContext::local_standalone(
             directory.clone(),
-            config.global,
+            global,
+            Arc::new(policy),
+            Arc::new(DiskFileSystem),
         ));
+        Self::with_global(global, filename, directory, config)
+    }
+
+    fn with_global(
+        global: Arc<ProcessingContext>,
+        filename: String,
+        directory: String,
+        config: GlitterConfig,
+    ) -> Self {
+        let root = Arc::new(ProcessingContext::initial(
+            filename.clone(),
+            directory,
+            config.local,
+            config.injection.clone(),
+            config.template,
+            Arc::clone(&global.policy),
+            Arc::clone(&global.file_system),
+        ));
+        let outputs =
+            Self::route_outputs(&global, &root, &filename, config.outputs, &config.injection);
+        let layout = Self::layout(
+            &global,
+            &root,
+            &filename,
+            config.extends,
+            config.blocks,
+            &config.injection,
+        );
+
         GlitterProcessor {
             global,
-            root: Rc::new(ProcessingContext::initial(
-                filename,
-                directory,
-                config.local,
-                config.injection,
-                config.template,
-            )),
+            root,
+            output: config.output,
+            outputs,
+            layout,
+            format: config.format,
         }
     }
 
+    fn route_outputs(
+        global: &Arc<ProcessingContext>,
+        root: &ProcessingContext,
+        filename: &str,
+        outputs: Vec<OutputDefinition>,
+        default_injection: &[VariableDefinitionBlock],
+    ) -> Vec<RoutedOutput> {
+        outputs
+            .into_iter()
+            .map(|output| RoutedOutput {
+                file: output.file,
+                format: output.format,
+                processor: Self::sibling(
+                    global,
+                    root,
+                    filename,
+                    output
+                        .injection
+                        .unwrap_or_else(|| default_injection.to_vec()),
+                    output.template,
+                ),
+            })
+            .collect()
+    }
+
+    fn layout(
+        global: &Arc<ProcessingContext>,
+        root: &ProcessingContext,
+        filename: &str,
+        extends: Option<String>,
+        blocks: BTreeMap<String, TemplateDefinition>,
+        injection: &[VariableDefinitionBlock],
+    ) -> Option<Layout> {
+        Some(Layout {
+            file: extends?,
+            blocks: blocks
+                .into_iter()
+                .map(|(name, template)| {
+                    let block = Self::sibling(global, root, filename, injection.to_vec(), template);
+                    (name, block)
+                })
+                .collect(),
+            extended: vec![],
+        })
+    }
+
+    /// Renders another template with the variables of `root`
+    fn sibling(
+        global: &Arc<ProcessingContext>,
+        root: &ProcessingContext,
+        filename: &str,
+        injection: Vec<VariableDefinitionBlock>,
+        template: TemplateDefinition,
+    ) -> Self {
+        GlitterProcessor {
+            global: Arc::clone(global),
+            root: Arc::new(root.sibling(filename, injection, template)),
+            output: None,
+            outputs: vec![],
+            layout: None,
+            format: OutputFormat::default(),
+        }
+    }
+
+    /// One processor per document of a stream. All of them share one global store,
+    /// made up of the `global` sections of all documents (first definition wins).
+    /// `$now` and `$today` are taken from `now`, by default `SOURCE_DATE_EPOCH` or the
+    /// current time.
+    pub fn stream(
+        filename: String,
+        directory: String,
+        configs: Vec<GlitterConfig>,
+        policy: AccessPolicy,
+        file_system: Arc<dyn FileSystem>,
+        now: Option<SystemTime>,
+    ) -> Vec<Self> {
+        let mut global = VariableDefinitionBlock::new();
+        for config in &configs {
+            for (key, definition) in &config.global {
+                global
+                    .entry(key.clone())
+                    .or_insert_with(|| definition.clone());
+            }
+        }
+        define_clock(&mut global, now);
+
+        let global = Arc::new(ProcessingContext::local_standalone(
+            directory.clone(),
+            global,
+            Arc::new(policy),
+            file_system,
+        ));
+        configs
+            .into_iter()
+            .map(|config| {
+                Self::with_global(
+                    Arc::clone(&global),
+                    filename.clone(),
+                    directory.clone(),
+                    config,
+                )
+            })
+            .collect()
+    }
+
+    /// The file this processor's output should be written to, instead of the main output
+    pub fn output_file(&self) -> Option<&str> {
+        self.output.as_deref()
+    }
+
+    /// What the config may read, execute and write
+    pub(crate) fn policy(&self) -> &AccessPolicy {
+        &self.root.policy
+    }
+
+    /// Newlines, encoding and BOM of the output, as far as the config sets them
+    pub fn output_format(&self) -> &OutputFormat {
+        &self.format
+    }
+
     fn subprocessor(
         &self,
         filename: String,
         directory: String,
         config: GlitterConfig,
         parameter: VariableDefinitionBlock,
-        source_context: Rc<ProcessingContext>,
+        source_context: Arc<ProcessingContext>,
     ) -> Self {
-        let global = Rc::clone(&self.global);
+        let global = Arc::clone(&self.global);
+        let root = Arc::new(ProcessingContext::subcontext(
+            filename.clone(),
+            directory,
+            config.local,
+            config.injection.clone(),
+            config.template,
+            parameter,
+            source_context,
+        ));
+        let outputs =
+            Self::route_outputs(&global, &root, &filename, config.outputs, &config.injection);
+        let layout = Self::layout(
+            &global,
+            &root,
+            &filename,
+            config.extends,
+            config.blocks,
+            &config.injection,
+        );
+
         GlitterProcessor {
             global,
-            root: Rc::new(ProcessingContext::subcontext(
-                filename,
-                directory,
-                config.local,
-                config.injection,
-                config.template,
-                parameter,
-                source_context,
-            )),
+            root,
+            output: None,
+            outputs,
+            layout,
+            format: config.format,
         }
     }
 
@@ -94,53 +356,276 @@ impl GlitterProcessor {
         self.render(&mut renderer)
     }
 
-    pub(crate) fn render(
+    /// Renders the template into the renderer and every entry of `outputs` into its own file
+    pub fn render(&self, renderer: &mut TemplateRenderer) -> Result<(), TemplateRenderError> {
+        let renderer = &mut renderer.formatted(&self.format);
+        self.render_template(renderer)?;
+
+        for output in &self.outputs {
+            let mut file = renderer
+                .create_output_file(&output.file, &output.format, &self.root.policy)
+                .context(OutputFileSnafu {
+                    file_name: output.file.clone(),
+                })?;
+            output
+                .processor
+                .render_template(&mut renderer.redirect(&mut file))?;
+            file.flush().context(OutputFileSnafu {
+                file_name: output.file.clone(),
+            })?;
+        }
+
+        Ok(())
+    }
+
+    /// Renders only the template itself (placed into its layout), skipping the `outputs`
+    pub(crate) fn render_template(
+        &self,
+        renderer: &mut TemplateRenderer,
+    ) -> Result<(), TemplateRenderError> {
+        match &self.layout {
+            Some(layout) => self.render_into_layout(layout, renderer),
+            None => self.render_own_template(renderer),
+        }
+    }
+
+    /// Renders the layout with `$content` and `$block.<name>` available to its template
+    fn render_into_layout(
+        &self,
+        layout: &Layout,
+        renderer: &mut TemplateRenderer,
+    ) -> Result<(), TemplateRenderError> {
+        let content = Self::render_to_string(renderer, |buffer_renderer| {
+            self.render_own_template(buffer_renderer)
+        })?;
+
+        let mut blocks = VariableDefinitionBlock::new();
+        for (name, block) in &layout.blocks {
+            let rendered_block = Self::render_to_string(renderer, |buffer_renderer| {
+                block.render_template(buffer_renderer)
+            })?;
+            blocks.insert(
+                name.clone(),
+                ValueDefinition::Value(RawValue::String(rendered_block)),
+            );
+        }
+
+        let mut slots = VariableDefinitionBlock::new();
+        slots.insert(
+            String::from("$content"),
+            ValueDefinition::Value(RawValue::String(content)),
+        );
+        slots.insert(String::from("$block"), ValueDefinition::Object(blocks));
+
+        let layout_processor = self
+            .layout_processor(layout, slots)
+            .context(LayoutFailedSnafu {
+                file_name: layout.file.clone(),
+            })?;
+        layout_processor.render(renderer)
+    }
+
+    fn render_to_string(
+        renderer: &mut TemplateRenderer,
+        render: impl FnOnce(&mut TemplateRenderer) -> Result<(), TemplateRenderError>,
+    ) -> Result<String, TemplateRenderError> {
+        let mut buffer = Vec::<u8>::new();
+        render(&mut renderer.redirect(&mut buffer))?;
+
+        String::from_utf8(buffer).context(InvalidLayoutContentSnafu)
+    }
+
+    /// The layout gets the slots as injection variables, next to `$iteration` & co.
+    /// A layout without injections is rendered once.
+    fn layout_processor(
+        &self,
+        layout: &Layout,
+        slots: VariableDefinitionBlock,
+    ) -> Result<GlitterProcessor, ValueRenderError> {
+        let file = &layout.file;
+        let full_file_path = self
+            .root
+            .resolve_filename(file)
+            .context(AccessDeniedSnafu)?;
+        let layout_path = canonical(Path::new(&full_file_path));
+        if layout.extended.contains(&layout_path) {
+            return LayoutCycleSnafu { file_name: file }.fail();
+        }
+        let (filename, directory, mut config) =
+            self.read_sub_config(full_file_path, DocumentFormat::resolve(None, file))?;
+
+        if config.injection.is_empty() {
+            config.injection.push(VariableDefinitionBlock::new());
+        }
+        for injection in &mut config.injection {
+            injection.extend(slots.clone());
+        }
+
+        let mut processor = self.subprocessor(
+            filename,
+            directory,
+            config,
+            VariableDefinitionBlock::new(),
+            Arc::clone(&self.root),
+        );
+        if let Some(outer_layout) = &mut processor.layout {
+            outer_layout.extended = layout.extended.clone();
+            outer_layout.extended.push(layout_path);
+        }
+
+        Ok(processor)
+    }
+
+    fn render_own_template(
         &self,
         renderer: &mut TemplateRenderer,
     ) -> Result<(), TemplateRenderError> {
         let injection_count = self.root.injection.len();
 
         if let Some(template) = self.root.template.clone() {
-            renderer.render(&template, injection_count, Rc::clone(&self.root), self)?;
+            renderer.render(&template, injection_count, Arc::clone(&self.root), self)?;
         }
 
         Ok(())
     }
 
-    fn read_variable(
-        storages: &[(Rc<VariableStore>, RequestSource)],
+    /// Resolves every variable of the global, local and injection blocks.
+    /// With `evaluate_instructions` unset `execute` and `render` are reported as unevaluated.
+    pub fn dump_variables(
+        &self,
+        evaluate_instructions: bool,
+    ) -> Result<VariableDump, ValueRenderError> {
+        let global = self.dump_block(
+            &self.global,
+            self.global.local.keys(),
+            RequestSource::Global,
+            evaluate_instructions,
+        )?;
+        let local = self.dump_block(
+            &self.root,
+            self.root.local.keys(),
+            RequestSource::Local,
+            evaluate_instructions,
+        )?;
+        let injection = self
+            .root
+            .injection
+            .iter()
+            .enumerate()
+            .map(|(iteration_count, store)| {
+                self.dump_block(
+                    &self.root,
+                    store.keys(),
+                    RequestSource::Template(iteration_count),
+                    evaluate_instructions,
+                )
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(VariableDump {
+            global,
+            local,
+            injection,
+        })
+    }
+
+    fn dump_block(
+        &self,
+        context: &Arc<ProcessingContext>,
+        keys: Vec<String>,
+        request_source: RequestSource,
+        evaluate_instructions: bool,
+    ) -> Result<ResolvedBlock, ValueRenderError> {
+        keys.into_iter()
+            .map(|key| {
+                let value = self.dump_path(
+                    context,
+                    vec![key.clone()],
+                    &request_source,
+                    evaluate_instructions,
+                )?;
+                Ok((key, value))
+            })
+            .collect()
+    }
+
+    fn dump_path(
+        &self,
+        context: &Arc<ProcessingContext>,
+        path: Vec<String>,
+        request_source: &RequestSource,
+        evaluate_instructions: bool,
+    ) -> Result<ResolvedValue, ValueRenderError> {
+        match self.resolve_node(
+            Arc::clone(context),
+            ValuePath(path.clone()),
+            request_source.clone(),
+            evaluate_instructions,
+        )? {
+            ResolvedNode::Value(value) => Ok(ResolvedValue::Value(value.calculate()?)),
+            ResolvedNode::Unevaluated { description, .. } => {
+                Ok(ResolvedValue::Unevaluated(description))
+            }
+            ResolvedNode::Macro { definition, .. } => Ok(ResolvedValue::Unevaluated(format!(
+                "macro({})",
+                definition.arguments.join(", ")
+            ))),
+            ResolvedNode::Object { keys, .. } => {
+                let mut block = ResolvedBlock::new();
+                for key in keys {
+                    let mut sub_path = path.clone();
+                    sub_path.push(key.clone());
+                    block.insert(
+                        key,
+                        self.dump_path(context, sub_path, request_source, evaluate_instructions)?,
+                    );
+                }
+
+                Ok(ResolvedValue::Object(block))
+            }
+        }
+    }
+
+    fn read_variable<'s>(
+        storages: impl IntoIterator<Item = (&'s VariableStore, RequestSource)>,
         variable_path: &mut ValuePath,
     ) -> (StoredVariable, RequestSource) {
         let first_key = &variable_path[0];
         for (single_storage, next_request_source) in storages {
             if single_storage.contains(first_key) {
-                return (
-                    single_storage.resolve(variable_path),
-                    next_request_source.clone(),
-                );
+                return (single_storage.resolve(variable_path), next_request_source);
             }
         }
 
         (StoredVariable::Missing, RequestSource::Global)
     }
 
-    fn storages_for(
-        global: &Rc<ProcessingContext>,
-        context: &ProcessingContext,
+    /// The storages in lookup order. Templates see the injections of all iterations up to
+    /// their own, which are only visited as far as needed.
+    fn storages_for<'c>(
+        global: &'c ProcessingContext,
+        context: &'c ProcessingContext,
         request_source: &RequestSource,
-    ) -> Vec<(Rc<VariableStore>, RequestSource)> {
+    ) -> Box<dyn Iterator<Item = (&'c VariableStore, RequestSource)> + 'c> {
         match request_source {
-            RequestSource::Template(iteration_count) => (0..=*iteration_count)
-                .rev()
-                .map(|i| (Rc::clone(&context.injection[i]), RequestSource::Injection))
-                .collect::<Vec<_>>(),
-            RequestSource::Injection | RequestSource::Local => vec![
-                (Rc::clone(&context.local), RequestSource::Local),
-                (Rc::clone(&global.local), RequestSource::Global),
-            ],
-            RequestSource::Global => vec![(Rc::clone(&global.local), RequestSource::Global)],
+            RequestSource::Template(iteration_count) => Box::new(
+                context.injection[..=*iteration_count]
+                    .iter()
+                    .rev()
+                    .map(|store| (&**store, RequestSource::Injection)),
+            ),
+            RequestSource::Injection | RequestSource::Local => Box::new(
+                [
+                    (&*context.local, RequestSource::Local),
+                    (&*global.local, RequestSource::Global),
+                ]
+                .into_iter(),
+            ),
+            RequestSource::Global => {
+                Box::new(std::iter::once((&*global.local, RequestSource::Global)))
+            }
             RequestSource::CallingContext => {
-                vec![(Rc::clone(&context.local), RequestSource::Local)]
+                Box::new(std::iter::once((&*context.local, RequestSource::Local)))
             }
         }
     }
@@ -148,9 +633,21 @@ impl GlitterProcessor {
     fn sub_processor_for(
         &self,
         full_file_path: String,
+        format: DocumentFormat,
         parameter: VariableDefinitionBlock,
-        source_context: Rc<ProcessingContext>,
+        source_context: Arc<ProcessingContext>,
     ) -> Result<GlitterProcessor, ValueRenderError> {
+        let (filename, directory, config) = self.read_sub_config(full_file_path, format)?;
+
+        Ok(self.subprocessor(filename, directory, config, parameter, source_context))
+    }
+
+    /// Reads the config of another glitter file, along with its name and directory
+    fn read_sub_config(
+        &self,
+        full_file_path: String,
+        format: DocumentFormat,
+    ) -> Result<(String, String, GlitterConfig), ValueRenderError> {
         let path = std::path::Path::new(&full_file_path);
         let filename = if let Some(name) = path.file_name() {
             name.to_str().unwrap().to_owned()
@@ -163,51 +660,67 @@ impl GlitterProcessor {
             return FailedAccessingFilesystemSnafu {}.fail();
         };
 
-        let mut input =
-            BufReader::new(File::open(full_file_path).expect("Could not open input file"));
-        let config_reader = ConfigReader::new();
+        let mut input = self
+            .global
+            .open_file(&full_file_path)
+            .context(FailedReadingTextSnafu {
+                input_file: full_file_path.clone(),
+            })?;
+        let config_reader = ConfigReader::for_format(format);
         let config = config_reader
             .read(&mut input)
             .context(InvalidSubRenderConfigSnafu)?;
 
-        Ok(self.subprocessor(filename, directory, config, parameter, source_context))
+        Ok((filename, directory, config))
     }
 
     fn import_yaml(
         &self,
         full_file_path: String,
-    ) -> Result<Rc<ProcessingContext>, ValueRenderError> {
+        format: DocumentFormat,
+    ) -> Result<Arc<ProcessingContext>, ValueRenderError> {
         let path = std::path::Path::new(&full_file_path);
         let directory = Self::extract_parent_directory(path)?;
 
-        let input_file = File::open(full_file_path).expect("Could not open input file");
-        let mut input_reader = BufReader::new(input_file);
-        let imported_vals = YamlImporter::new().read(&mut input_reader).unwrap();
+        let mut input_reader =
+            self.global
+                .open_file(&full_file_path)
+                .context(FailedReadingTextSnafu {
+                    input_file: full_file_path,
+                })?;
+        let imported_vals = YamlImporter::for_format(format)
+            .read(&mut input_reader)
+            .context(ImportCommandFailedSnafu)?;
 
-        Ok(Rc::new(ProcessingContext::local_standalone(
+        Ok(Arc::new(ProcessingContext::local_standalone(
             directory,
             imported_vals,
+            Arc::clone(&self.global.policy),
+            Arc::clone(&self.global.file_system),
         )))
     }
 
     fn load(
         &self,
         full_file_path: String,
+        format: DocumentFormat,
         parameter: VariableDefinitionBlock,
-        source_context: Rc<ProcessingContext>,
-    ) -> Result<Rc<ProcessingContext>, ValueRenderError> {
+        source_context: Arc<ProcessingContext>,
+    ) -> Result<Arc<ProcessingContext>, ValueRenderError> {
         let path = std::path::Path::new(&full_file_path);
         let directory = Self::extract_parent_directory(path)?;
 
-        let input_file = File::open(&full_file_path).context(FailedReadingTextSnafu {
-            input_file: full_file_path,
-        })?;
-        let mut input_reader = BufReader::new(input_file);
-        let loaded_vals = ConfigReader::new()
+        let mut input_reader =
+            source_context
+                .open_file(&full_file_path)
+                .context(FailedReadingTextSnafu {
+                    input_file: full_file_path,
+                })?;
+        let loaded_vals = ConfigReader::for_format(format)
             .load(&mut input_reader)
             .context(LoadCommandFailedSnafu)?;
 
-        Ok(Rc::new(ProcessingContext::local_subcontext(
+        Ok(Arc::new(ProcessingContext::local_subcontext(
             directory,
             loaded_vals,
             parameter,
@@ -226,7 +739,7 @@ impl GlitterProcessor {
     fn convert_definition(
         &self,
         definition: ValueDefinition,
-        context: Rc<ProcessingContext>,
+        context: Arc<ProcessingContext>,
         request_source: RequestSource,
         variable_path: String,
     ) -> Result<Box<dyn RenderableVariable>, ValueRenderError> {
@@ -236,28 +749,58 @@ impl GlitterProcessor {
         match self
             .process_variable(var, &context, request_source)
             .context(FailedProcessingVariableSnafu {
-                var_resolution_path: vec![variable_path],
+                var_resolution_path: vec![variable_path.clone()],
             })? {
             NextVarProcessingInstruction::ReportMissing => {
                 panic!("Can't be happening. There is no var name, so it can't be not resolved")
             }
             NextVarProcessingInstruction::ReturnValue(value) => Ok(value),
+            NextVarProcessingInstruction::ReturnMacro(definition, context) => {
+                Ok(Box::new(self.expand_macro(
+                    variable_path,
+                    definition,
+                    &context,
+                    VariableDefinitionBlock::new(),
+                )?))
+            }
+            NextVarProcessingInstruction::ReturnObject(_) => FailedResolvingVariableSnafu {
+                var_resolution_path: vec![variable_path],
+            }
+            .fail(),
             NextVarProcessingInstruction::ContinueElsewhere(
                 _,
                 new_variable_path,
                 new_request_source,
-            ) => return self.resolve_var(new_variable_path, new_request_source),
+            ) => self.resolve_var(new_variable_path, new_request_source),
         }
     }
 
+    /// Converts the definition and calculates its value right away
+    fn calculate_definition(
+        &self,
+        definition: ValueDefinition,
+        context: &Arc<ProcessingContext>,
+        request_source: &RequestSource,
+        variable_path: String,
+    ) -> Result<RawValue, ValueRenderError> {
+        self.convert_definition(
+            definition,
+            Arc::clone(context),
+            request_source.clone(),
+            variable_path,
+        )?
+        .calculate()
+    }
+
     fn process_variable(
         &self,
         variable: StoredVariable,
-        context: &Rc<ProcessingContext>,
+        context: &Arc<ProcessingContext>,
         request_source: RequestSource,
     ) -> Result<NextVarProcessingInstruction, ValueRenderError> {
         match variable {
             StoredVariable::Missing => Ok(NextVarProcessingInstruction::ReportMissing),
+            StoredVariable::Object(keys) => Ok(NextVarProcessingInstruction::ReturnObject(keys)),
             StoredVariable::Value(actual_value) => Ok(NextVarProcessingInstruction::ReturnValue(
                 Box::new(RenderableRawValue::from(actual_value)),
             )),
@@ -272,25 +815,32 @@ impl GlitterProcessor {
                 ))
             }
             StoredVariable::Instruction(instruction, new_path) => match instruction {
-                ProcessingInstruction::Quote(file) => {
+                ProcessingInstruction::Macro(definition) if new_path.is_empty() => Ok(
+                    NextVarProcessingInstruction::ReturnMacro(definition, Arc::clone(context)),
+                ),
+                ProcessingInstruction::Macro(_) => Ok(NextVarProcessingInstruction::ReportMissing),
+                ProcessingInstruction::Quote(quote_statement) => {
                     Ok(NextVarProcessingInstruction::ReturnValue(Box::new(
-                        RenderableQuote::from(file, Rc::clone(context)),
+                        RenderableQuote::from(quote_statement, Arc::clone(context)),
                     )))
                 }
-                ProcessingInstruction::Import(file) => {
-                    let import_context =
-                        self.import_yaml(self.resolve_filename(&file, &request_source, context))?;
+                ProcessingInstruction::Import(import_statement) => {
+                    let import_context = self.import_yaml(
+                        self.resolve_filename(&import_statement.file, &request_source, context)?,
+                        DocumentFormat::resolve(import_statement.format, &import_statement.file),
+                    )?;
                     Ok(NextVarProcessingInstruction::ContinueElsewhere(
                         Some(import_context),
                         new_path,
                         RequestSource::CallingContext,
                     ))
                 }
-                ProcessingInstruction::Load(file, parameter) => {
+                ProcessingInstruction::Load(load_statement) => {
                     let load_context = self.load(
-                        self.resolve_filename(&file, &request_source, context),
-                        parameter,
-                        Rc::clone(context),
+                        self.resolve_filename(&load_statement.file, &request_source, context)?,
+                        DocumentFormat::resolve(load_statement.format, &load_statement.file),
+                        load_statement.parameter,
+                        Arc::clone(context),
                     )?;
 
                     Ok(NextVarProcessingInstruction::ContinueElsewhere(
@@ -299,60 +849,152 @@ impl GlitterProcessor {
                         RequestSource::CallingContext,
                     ))
                 }
-                ProcessingInstruction::Render(file, parameter) => {
-                    let subprocessor = self.sub_processor_for(
-                        self.resolve_filename(&file, &request_source, context),
-                        parameter,
-                        Rc::clone(context),
-                    )?;
+                ProcessingInstruction::Render(render_statement) => {
+                    let render = render_statement.source.to_string();
+                    let subprocessor = match render_statement.source {
+                        RenderSource::File(file) => self.sub_processor_for(
+                            self.resolve_filename(&file, &request_source, context)?,
+                            DocumentFormat::resolve(render_statement.format, &file),
+                            render_statement.parameter,
+                            Arc::clone(context),
+                        )?,
+                        RenderSource::Inline(config) => {
+                            let directory = if request_source == RequestSource::Global {
+                                self.global.directory.clone()
+                            } else {
+                                context.directory.clone()
+                            };
+
+                            self.subprocessor(
+                                String::from(INLINE_RENDER_FILENAME),
+                                directory,
+                                *config,
+                                render_statement.parameter,
+                                Arc::clone(context),
+                            )
+                        }
+                    };
+                    // its value may be calculated without a place to write files to
+                    if !subprocessor.outputs.is_empty() {
+                        return SubRenderWithOutputsSnafu { render }.fail();
+                    }
 
                     Ok(NextVarProcessingInstruction::ReturnValue(Box::new(
-                        SubRender::from(subprocessor),
+                        SubRender::routed(subprocessor, render_statement.output),
                     )))
                 }
-                ProcessingInstruction::Execute(executable, arguments) => {
-                    let mut has_error = false;
-                    let mut index = -1;
-                    let rendered_values = arguments
+                ProcessingInstruction::Execute(execute_statement) => {
+                    let arguments = execute_statement
+                        .arguments
                         .into_iter()
-                        .map(|arg| {
-                            index += 1;
-                            match self.convert_definition(
-                                arg,
-                                Rc::clone(context),
-                                request_source.clone(),
-                                format!("[{}]", &index),
-                            ) {
-                                Ok(renderable) => renderable.calculate(),
-                                Err(e) => {
-                                    has_error = true;
-                                    Err(e)
-                                }
-                            }
+                        .enumerate()
+                        .map(|(index, argument)| {
+                            self.calculate_definition(
+                                argument,
+                                context,
+                                &request_source,
+                                format!("[{}]", index),
+                            )
                         })
-                        .collect::<Vec<_>>();
-
-                    if has_error {
-                        let error = rendered_values
-                            .into_iter()
-                            .find(|v| v.is_err())
-                            .unwrap()
-                            .unwrap_err();
-                        return Err(error);
-                    }
+                        .collect::<Result<Vec<_>, _>>()?;
 
-                    let rendered_argument_list = rendered_values
+                    let stdin = execute_statement
+                        .stdin
+                        .map(|stdin| {
+                            self.calculate_definition(
+                                *stdin,
+                                context,
+                                &request_source,
+                                String::from("stdin"),
+                            )
+                        })
+                        .transpose()?;
+                    let env = execute_statement
+                        .env
                         .into_iter()
-                        .map(|v| v.unwrap())
-                        .collect::<Vec<_>>();
+                        .map(|(name, value)| {
+                            let value = self.calculate_definition(
+                                value,
+                                context,
+                                &request_source,
+                                format!("env.{}", name),
+                            )?;
+                            Ok((name, value.to_string()))
+                        })
+                        .collect::<Result<Vec<_>, _>>()?;
+                    let cwd = execute_statement
+                        .cwd
+                        .map(|cwd| {
+                            self.calculate_definition(
+                                *cwd,
+                                context,
+                                &request_source,
+                                String::from("cwd"),
+                            )
+                        })
+                        .transpose()?;
 
-                    Ok(NextVarProcessingInstruction::ReturnValue(Box::new(
-                        RenderableExecutionResult::from(
-                            executable,
-                            rendered_argument_list,
-                            Rc::clone(context),
-                        ),
-                    )))
+                    let execution = RenderableExecutionResult::from(
+                        execute_statement.target,
+                        arguments,
+                        ExecuteEnvironment {
+                            stdin: stdin.map(|stdin| stdin.to_string()),
+                            env,
+                            cwd: cwd
+                                .map(|cwd| {
+                                    self.resolve_filename(
+                                        &cwd.to_string(),
+                                        &request_source,
+                                        context,
+                                    )
+                                })
+                                .transpose()?,
+                            clear_env: execute_statement.clear_env,
+                        },
+                        execute_statement.options,
+                        // paths of the command are relative to the file defining it
+                        if request_source == RequestSource::Global {
+                            Arc::clone(&self.global)
+                        } else {
+                            Arc::clone(context)
+                        },
+                    );
+
+                    let result_block = match execute_statement.structure {
+                        ResultStructure::Text => {
+                            return Ok(NextVarProcessingInstruction::ReturnValue(Box::new(
+                                execution,
+                            )))
+                        }
+                        ResultStructure::Lines => execution
+                            .calculate()?
+                            .to_string()
+                            .lines()
+                            .enumerate()
+                            .map(|(index, line)| {
+                                (
+                                    index.to_string(),
+                                    ValueDefinition::Value(RawValue::String(line.to_owned())),
+                                )
+                            })
+                            .collect::<VariableDefinitionBlock>(),
+                        ResultStructure::Parsed(format) => YamlImporter::for_format(format)
+                            .read_str(&execution.calculate()?.to_string())
+                            .context(ExecuteResultUnparsableSnafu {
+                                executable: execution.describe(),
+                            })?,
+                    };
+
+                    Ok(NextVarProcessingInstruction::ContinueElsewhere(
+                        Some(Arc::new(ProcessingContext::local_standalone(
+                            context.directory.clone(),
+                            result_block,
+                            Arc::clone(&context.policy),
+                            Arc::clone(&context.file_system),
+                        ))),
+                        new_path,
+                        RequestSource::CallingContext,
+                    ))
                 }
             },
         }
@@ -360,20 +1002,76 @@ impl GlitterProcessor {
 
     fn resolve_var(
         &self,
+        variable_path: ValuePath,
+        request_source: RequestSource,
+    ) -> Result<Box<dyn RenderableVariable>, ValueRenderError> {
+        let name = variable_path.render();
+        match self.resolve_node(Arc::clone(&self.root), variable_path, request_source, true)? {
+            ResolvedNode::Value(value) => Ok(value),
+            ResolvedNode::Macro {
+                definition,
+                context,
+            } => Ok(Box::new(self.expand_macro(
+                name,
+                definition,
+                &context,
+                VariableDefinitionBlock::new(),
+            )?)),
+            ResolvedNode::Object {
+                resolution_path, ..
+            }
+            | ResolvedNode::Unevaluated {
+                resolution_path, ..
+            } => FailedResolvingVariableSnafu {
+                var_resolution_path: resolution_path,
+            }
+            .fail(),
+        }
+    }
+
+    fn resolve_node(
+        &self,
+        mut context: Arc<ProcessingContext>,
         mut variable_path: ValuePath,
         mut request_source: RequestSource,
-    ) -> Result<Box<dyn RenderableVariable>, ValueRenderError> {
-        let mut context = Rc::clone(&self.root);
-        let mut storages;
+        evaluate_instructions: bool,
+    ) -> Result<ResolvedNode, ValueRenderError> {
         let mut current_variable_path: String;
         let mut path_history = vec![];
 
         loop {
             current_variable_path = variable_path.render();
             path_history.push(current_variable_path.clone());
-            storages = Self::storages_for(&self.global, &context, &request_source);
 
-            let (result, next_request_source) = Self::read_variable(&storages, &mut variable_path);
+            if variable_path.is_empty() {
+                return Ok(ResolvedNode::Object {
+                    keys: context.local.keys(),
+                    resolution_path: path_history,
+                });
+            }
+
+            let storages = Self::storages_for(&self.global, &context, &request_source);
+            let (result, next_request_source) = Self::read_variable(storages, &mut variable_path);
+            if !evaluate_instructions {
+                if let StoredVariable::Instruction(instruction, _) = &result {
+                    match instruction {
+                        ProcessingInstruction::Execute(execute_statement) => {
+                            return Ok(ResolvedNode::Unevaluated {
+                                description: format!("execute: {}", execute_statement.target),
+                                resolution_path: path_history,
+                            })
+                        }
+                        ProcessingInstruction::Render(render_statement) => {
+                            return Ok(ResolvedNode::Unevaluated {
+                                description: format!("render: {}", render_statement.source),
+                                resolution_path: path_history,
+                            })
+                        }
+                        _ => (),
+                    }
+                }
+            }
+
             return match self
                 .process_variable(result, &context, next_request_source)
                 .context(FailedProcessingVariableSnafu {
@@ -383,7 +1081,17 @@ impl GlitterProcessor {
                     var_resolution_path: path_history,
                 }
                 .fail(),
-                NextVarProcessingInstruction::ReturnValue(value) => Ok(value),
+                NextVarProcessingInstruction::ReturnValue(value) => Ok(ResolvedNode::Value(value)),
+                NextVarProcessingInstruction::ReturnObject(keys) => Ok(ResolvedNode::Object {
+                    keys,
+                    resolution_path: path_history,
+                }),
+                NextVarProcessingInstruction::ReturnMacro(definition, context) => {
+                    Ok(ResolvedNode::Macro {
+                        definition,
+                        context,
+                    })
+                }
                 NextVarProcessingInstruction::ContinueElsewhere(
                     new_context_option,
                     new_variable_path,
@@ -401,17 +1109,124 @@ impl GlitterProcessor {
         }
     }
 
+    /// Macros are looked up in the injection of the calling template,
+    /// then in the local and global variables
+    fn call_macro(
+        &self,
+        call: MacroCall,
+        iteration_count: usize,
+    ) -> Result<MacroExpansion, ValueRenderError> {
+        let path = ValuePath::from(&call.name);
+        let template_source = RequestSource::Template(iteration_count);
+        let in_injection = self.root.injection[..=iteration_count]
+            .iter()
+            .any(|store| store.contains(&path[0]));
+        let request_source = if in_injection {
+            template_source.clone()
+        } else {
+            RequestSource::Local
+        };
+
+        let ResolvedNode::Macro {
+            definition,
+            context,
+        } = self.resolve_node(Arc::clone(&self.root), path, request_source, true)?
+        else {
+            return NotAMacroSnafu { name: call.name }.fail();
+        };
+
+        let evaluate = |argument| match argument {
+            MacroArgument::Literal(value) => Ok(value),
+            MacroArgument::Variable(path) => self
+                .resolve_var(ValuePath::from(&path), template_source.clone())?
+                .calculate(),
+        };
+
+        let mut arguments = VariableDefinitionBlock::new();
+        match call.arguments {
+            MacroArguments::Positional(values) => {
+                if values.len() != definition.arguments.len() {
+                    return MacroArgumentCountSnafu {
+                        name: call.name,
+                        expected: definition.arguments.len(),
+                        given: values.len(),
+                    }
+                    .fail();
+                }
+                for (name, value) in definition.arguments.iter().zip(values) {
+                    arguments.insert(name.clone(), ValueDefinition::Value(evaluate(value)?));
+                }
+            }
+            MacroArguments::Named(values) => {
+                for (name, value) in values {
+                    if !definition.arguments.contains(&name) {
+                        return UnknownMacroArgumentSnafu {
+                            name: call.name,
+                            argument: name,
+                        }
+                        .fail();
+                    }
+                    arguments.insert(name, ValueDefinition::Value(evaluate(value)?));
+                }
+            }
+        }
+
+        self.expand_macro(call.name, definition, &context, arguments)
+    }
+
+    /// Binds the arguments as the only injection variables of the macro body
+    fn expand_macro(
+        &self,
+        name: String,
+        definition: MacroDefinition,
+        context: &ProcessingContext,
+        arguments: VariableDefinitionBlock,
+    ) -> Result<MacroExpansion, ValueRenderError> {
+        if let Some(missing) = definition
+            .arguments
+            .iter()
+            .find(|argument| !arguments.contains_key(*argument))
+        {
+            return MissingMacroArgumentSnafu {
+                name,
+                argument: missing.clone(),
+            }
+            .fail();
+        }
+
+        let processor = GlitterProcessor {
+            global: Arc::clone(&self.global),
+            root: Arc::new(context.macro_scope(MACRO_FILENAME, arguments)),
+            output: None,
+            outputs: vec![],
+            layout: None,
+            format: OutputFormat::default(),
+        };
+
+        Ok(MacroExpansion::new(name, processor, definition.template))
+    }
+
+    /// Renders a template value once with the first injection of this processor
+    pub(crate) fn render_fragment(
+        &self,
+        template: &TemplateValue,
+        renderer: &mut TemplateRenderer,
+    ) -> Result<(), TemplateRenderError> {
+        renderer.render_fragment(template, Arc::clone(&self.root), self)
+    }
+
     fn resolve_filename(
         &self,
         filename: &str,
         request_source: &RequestSource,
-        current_context: &Rc<ProcessingContext>,
-    ) -> String {
+        current_context: &Arc<ProcessingContext>,
+    ) -> Result<String, ValueRenderError> {
         if *request_source == RequestSource::Global {
             self.global.resolve_filename(filename)
         } else {
             current_context.resolve_filename(filename)
         }
+        .context(AccessDeniedSnafu)
     }
 }
 
@@ -424,13 +1239,74 @@ impl ValueRenderer for GlitterProcessor {
     ) -> Result<(), ValueRenderError> {
         let vp = ValuePath(
             variable_path
-                .split(|c| c == '.')
+                .split('.')
                 .map(|s| s.to_owned())
                 .collect::<Vec<_>>(),
         );
-        let value = self.resolve_var(vp, RequestSource::Template(iteration_count))?;
+        let value = match MacroCall::parse(variable_path) {
+            Some(Ok(call)) => Box::new(self.call_macro(call, iteration_count)?),
+            Some(Err(call)) => return InvalidMacroCallSnafu { call }.fail(),
+            None => self.resolve_var(vp, RequestSource::Template(iteration_count))?,
+        };
         value.render(output)?;
 
         Ok(())
     }
+
+    fn is_markup(&self, variable_path: &str) -> bool {
+        MacroCall::parse(variable_path).is_some()
+    }
+
+    /// Values of instructions are only known once evaluated, so only the instruction itself
+    /// counts, and references are followed like in `is_independent`
+    fn has_variable(&self, variable_path: &str, iteration_count: usize) -> bool {
+        let mut variable_path = ValuePath::from(&variable_path.to_owned());
+        let mut request_source = RequestSource::Template(iteration_count);
+        loop {
+            let storages = Self::storages_for(&self.global, &self.root, &request_source);
+            match Self::read_variable(storages, &mut variable_path) {
+                (StoredVariable::Value(_) | StoredVariable::Object(_), _) => return true,
+                (StoredVariable::Instruction(_, rest), _) => return rest.is_empty(),
+                (StoredVariable::LocalReference(path), next_request_source)
+                    if next_request_source != request_source =>
+                {
+                    variable_path = path;
+                    request_source = next_request_source;
+                }
+                _ => return false,
+            }
+        }
+    }
+
+    /// References are followed as long as they lead into another storage,
+    /// e.g. from the injection to a `render` in `local`
+    fn is_independent(&self, variable_path: &str, iteration_count: usize) -> bool {
+        if MacroCall::parse(variable_path).is_some() {
+            return false;
+        }
+
+        let mut variable_path = ValuePath::from(&variable_path.to_owned());
+        let mut request_source = RequestSource::Template(iteration_count);
+        loop {
+            let storages = Self::storages_for(&self.global, &self.root, &request_source);
+            match Self::read_variable(storages, &mut variable_path) {
+                (
+                    StoredVariable::Instruction(
+                        ProcessingInstruction::Render(_)
+                        | ProcessingInstruction::Execute(_)
+                        | ProcessingInstruction::Load(_),
+                        _,
+                    ),
+                    _,
+                ) => return true,
+                (StoredVariable::LocalReference(path), next_request_source)
+                    if next_request_source != request_source =>
+                {
+                    variable_path = path;
+                    request_source = next_request_source;
+                }
+                _ => return false,
+            }
+        }
+    }
 }
@@ -0,0 +1,148 @@
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::str::FromStr;
+
+use yaml_rust::{yaml::Hash, Yaml, YamlEmitter};
+
+use crate::config::model::RawValue;
+
+pub type ResolvedBlock = BTreeMap<String, ResolvedValue>;
+
+/// A variable after all references, loads, imports and sub-renders have been followed
+#[derive(Clone, Debug)]
+pub enum ResolvedValue {
+    Value(RawValue),
+    Object(ResolvedBlock),
+    /// An `execute` or `render` which was deliberately not evaluated
+    Unevaluated(String),
+}
+
+/// The resolved values of all variable blocks of a glitter config
+#[derive(Clone, Debug)]
+pub struct VariableDump {
+    pub global: ResolvedBlock,
+    pub local: ResolvedBlock,
+    pub injection: Vec<ResolvedBlock>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DumpFormat {
+    Yaml,
+    Json,
+}
+
+impl VariableDump {
+    /// The dumps of all documents of a stream: one YAML document each, or for JSON an array
+    /// when there is more than one
+    pub fn write_stream<T: Write>(
+        dumps: &[VariableDump],
+        format: DumpFormat,
+        output: &mut T,
+    ) -> std::io::Result<()> {
+        match (format, dumps) {
+            (DumpFormat::Json, [_, _, ..]) => {
+                let documents = dumps.iter().map(Self::to_json).collect::<Vec<_>>();
+                serde_json::to_writer_pretty(&mut *output, &documents)?;
+                writeln!(output)
+            }
+            _ => dumps
+                .iter()
+                .try_for_each(|dump| dump.write_to(format, output)),
+        }
+    }
+
+    pub fn write_to<T: Write>(&self, format: DumpFormat, output: &mut T) -> std::io::Result<()> {
+        match format {
+            DumpFormat::Yaml => {
+                let mut rendered = String::new();
+                YamlEmitter::new(&mut rendered)
+                    .dump(&self.to_yaml())
+                    .map_err(|e| std::io::Error::other(format!("{:?}", e)))?;
+                writeln!(output, "{}", rendered)
+            }
+            DumpFormat::Json => {
+                serde_json::to_writer_pretty(&mut *output, &self.to_json())?;
+                writeln!(output)
+            }
+        }
+    }
+
+    fn to_yaml(&self) -> Yaml {
+        let mut document = Hash::new();
+        document.insert(
+            Yaml::String(String::from("global")),
+            block_to_yaml(&self.global),
+        );
+        document.insert(
+            Yaml::String(String::from("local")),
+            block_to_yaml(&self.local),
+        );
+        document.insert(
+            Yaml::String(String::from("injection")),
+            Yaml::Array(self.injection.iter().map(block_to_yaml).collect()),
+        );
+
+        Yaml::Hash(document)
+    }
+
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "global": block_to_json(&self.global),
+            "local": block_to_json(&self.local),
+            "injection": self.injection.iter().map(block_to_json).collect::<Vec<_>>(),
+        })
+    }
+}
+
+fn block_to_yaml(block: &ResolvedBlock) -> Yaml {
+    Yaml::Hash(
+        block
+            .iter()
+            .map(|(key, value)| (Yaml::String(key.clone()), value_to_yaml(value)))
+            .collect::<Hash>(),
+    )
+}
+
+fn value_to_yaml(value: &ResolvedValue) -> Yaml {
+    match value {
+        ResolvedValue::Value(RawValue::Boolean(bool_value)) => Yaml::Boolean(*bool_value),
+        ResolvedValue::Value(RawValue::Integer(int_value)) => Yaml::Integer(*int_value),
+        ResolvedValue::Value(RawValue::Float(real_as_string)) => Yaml::Real(real_as_string.clone()),
+        ResolvedValue::Value(RawValue::String(string_value)) => Yaml::String(string_value.clone()),
+        ResolvedValue::Object(block) => block_to_yaml(block),
+        ResolvedValue::Unevaluated(description) => Yaml::String(placeholder(description)),
+    }
+}
+
+fn block_to_json(block: &ResolvedBlock) -> serde_json::Value {
+    serde_json::Value::Object(
+        block
+            .iter()
+            .map(|(key, value)| (key.clone(), value_to_json(value)))
+            .collect(),
+    )
+}
+
+fn value_to_json(value: &ResolvedValue) -> serde_json::Value {
+    match value {
+        ResolvedValue::Value(RawValue::Boolean(bool_value)) => serde_json::Value::Bool(*bool_value),
+        ResolvedValue::Value(RawValue::Integer(int_value)) => serde_json::Value::from(*int_value),
+        ResolvedValue::Value(RawValue::Float(real_as_string)) => {
+            match serde_json::Number::from_str(real_as_string) {
+                Ok(number) => serde_json::Value::Number(number),
+                Err(_) => serde_json::Value::String(real_as_string.clone()),
+            }
+        }
+        ResolvedValue::Value(RawValue::String(string_value)) => {
+            serde_json::Value::String(string_value.clone())
+        }
+        ResolvedValue::Object(block) => block_to_json(block),
+        ResolvedValue::Unevaluated(description) => {
+            serde_json::Value::String(placeholder(description))
+        }
+    }
+}
+
+fn placeholder(description: &str) -> String {
+    format!("<unevaluated {}>", description)
+}
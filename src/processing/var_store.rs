@@ -1,25 +1,29 @@
-use std::rc::Rc;
+use std::sync::Arc;
 
-use crate::config::model::ValueDefinition;
-use crate::config::model::{RawValue, ValueDefinitionList};
+use crate::config::model::{
+    ExecuteStatement, ImportStatement, LoadStatement, MacroDefinition, QuoteStatement, RawValue,
+    RenderStatement, ValueDefinition,
+};
 use crate::processing::{ProcessingContext, ValuePath, VariableDefinitionBlock};
 
 pub(crate) enum ProcessingInstruction {
-    Load(String, VariableDefinitionBlock),
-    Render(String, VariableDefinitionBlock),
+    Load(LoadStatement),
+    Render(RenderStatement),
     //execute a binary file and return the stdout:
-    Execute(String, ValueDefinitionList),
-    //import a YAML file and return the content:
-    Import(String),
-    Quote(String),
+    Execute(ExecuteStatement),
+    //import a YAML, JSON or TOML file and return the content:
+    Import(ImportStatement),
+    Quote(QuoteStatement),
+    Macro(MacroDefinition),
     //TODO: Select(Vec<CaseClause>),
 }
 
 pub(crate) enum StoredVariable {
     Value(RawValue),
+    Object(Vec<String>),
     Instruction(ProcessingInstruction, ValuePath),
     LocalReference(ValuePath),
-    DistantReference(ValuePath, Rc<ProcessingContext>),
+    DistantReference(ValuePath, Arc<ProcessingContext>),
     Missing,
 }
 
@@ -41,39 +45,37 @@ impl StoredVariable {
     pub(crate) fn from(definition: &ValueDefinition, key_path: &mut ValuePath) -> Self {
         match definition {
             ValueDefinition::Value(value) => StoredVariable::Value(value.clone()),
+            ValueDefinition::Object(map) if key_path.is_empty() => {
+                StoredVariable::Object(map.keys().cloned().collect())
+            }
             ValueDefinition::Object(map) => StoredVariable::out_of_block(map, key_path),
             ValueDefinition::Variable(value_path) => {
                 let mut path_parts = ValuePath::from(value_path);
                 path_parts.append(key_path);
                 StoredVariable::LocalReference(path_parts)
             }
-            ValueDefinition::Quote(file_path) => StoredVariable::Instruction(
-                ProcessingInstruction::Quote(file_path.clone()),
+            ValueDefinition::Quote(quote_statement) => StoredVariable::Instruction(
+                ProcessingInstruction::Quote(quote_statement.clone()),
                 key_path.clone(),
             ),
-            ValueDefinition::Import(file_path) => StoredVariable::Instruction(
-                ProcessingInstruction::Import(file_path.clone()),
+            ValueDefinition::Import(import_statement) => StoredVariable::Instruction(
+                ProcessingInstruction::Import(import_statement.clone()),
                 key_path.clone(),
             ),
             ValueDefinition::Load(load_statement) => StoredVariable::Instruction(
-                ProcessingInstruction::Load(
-                    load_statement.file.clone(),
-                    load_statement.parameter.clone(),
-                ),
+                ProcessingInstruction::Load(load_statement.clone()),
                 key_path.clone(),
             ),
-            ValueDefinition::Render(load_statement) => StoredVariable::Instruction(
-                ProcessingInstruction::Render(
-                    load_statement.file.clone(),
-                    load_statement.parameter.clone(),
-                ),
+            ValueDefinition::Render(render_statement) => StoredVariable::Instruction(
+                ProcessingInstruction::Render(render_statement.clone()),
                 key_path.clone(),
             ),
             ValueDefinition::Execute(execute_statement) => StoredVariable::Instruction(
-                ProcessingInstruction::Execute(
-                    execute_statement.executable.clone(),
-                    execute_statement.arguments.clone(),
-                ),
+                ProcessingInstruction::Execute(execute_statement.clone()),
+                key_path.clone(),
+            ),
+            ValueDefinition::Macro(macro_definition) => StoredVariable::Instruction(
+                ProcessingInstruction::Macro(macro_definition.clone()),
                 key_path.clone(),
             ),
         }
@@ -83,14 +85,14 @@ impl StoredVariable {
 pub struct VariableStore {
     inner_store: VariableDefinitionBlock,
     parameter: VariableDefinitionBlock,
-    source_context: Option<Rc<ProcessingContext>>,
+    source_context: Option<Arc<ProcessingContext>>,
 }
 
 impl VariableStore {
     pub(crate) fn with_context(
         variable_definition: VariableDefinitionBlock,
         parameter: VariableDefinitionBlock,
-        source_context: Rc<ProcessingContext>,
+        source_context: Arc<ProcessingContext>,
     ) -> Self {
         VariableStore {
             inner_store: variable_definition,
@@ -100,8 +102,8 @@ impl VariableStore {
     }
 
     pub(crate) fn contains(&self, key: &String) -> bool {
-        self.inner_store.get(key).is_some()
-            || (self.source_context.is_some() && self.parameter.get(key).is_some())
+        self.inner_store.contains_key(key)
+            || (self.source_context.is_some() && self.parameter.contains_key(key))
     }
 
     pub(crate) fn resolve(&self, key_path: &mut ValuePath) -> StoredVariable {
@@ -114,7 +116,7 @@ impl VariableStore {
             match StoredVariable::out_of_block(&self.parameter, key_path) {
                 StoredVariable::Missing => (),
                 StoredVariable::LocalReference(path_parts) => {
-                    return StoredVariable::DistantReference(path_parts, Rc::clone(source_context))
+                    return StoredVariable::DistantReference(path_parts, Arc::clone(source_context))
                 }
                 v => return v,
             };
@@ -123,6 +125,20 @@ impl VariableStore {
         StoredVariable::Missing
     }
 
+    pub(crate) fn keys(&self) -> Vec<String> {
+        let mut keys = self.inner_store.keys().cloned().collect::<Vec<_>>();
+        if self.source_context.is_some() {
+            keys.extend(
+                self.parameter
+                    .keys()
+                    .filter(|key| !self.inner_store.contains_key(*key))
+                    .cloned(),
+            );
+        }
+
+        keys
+    }
+
     #[inline(always)]
     pub(crate) fn insert<T: Into<String>>(mut self, key: T, value: ValueDefinition) -> Self {
         self.inner_store.insert(key.into(), value);
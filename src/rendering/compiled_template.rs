@@ -0,0 +1,476 @@
+use std::collections::HashMap;
+use std::io::{BufRead, Cursor, ErrorKind, Read};
+use std::ops::Range;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use snafu::ResultExt;
+
+use crate::rendering::template_rendering::TemplateRendererConfig;
+use crate::rendering::value_format::is_format;
+use crate::rendering::{
+    EmptyProcessingBlockSnafu, InvalidTemplateFileSnafu, NonTerminatedProcessingBlockSnafu,
+    ProcessingStatement, TemplateRenderError,
+};
+
+/// A template split into literal text and processing blocks. It is scanned once and then
+/// rendered for every iteration.
+pub(crate) struct CompiledTemplate {
+    content: String,
+    tokens: Vec<TemplateToken>,
+    /// Found while scanning, raised after everything in front of it was rendered
+    error: Option<ScanError>,
+    /// Position of the content within the template, for error messages
+    offset: usize,
+}
+
+/// Template files up to this size are compiled and kept for the whole run,
+/// larger ones are read again for every iteration
+const CACHED_TEMPLATE_SIZE: u64 = 8 * 1024 * 1024;
+
+/// A template as read for one iteration
+pub(crate) enum LoadedTemplate {
+    Compiled(Arc<CompiledTemplate>),
+    /// Compiled line by line while rendering, so only the current line is kept in memory
+    Streamed {
+        reader: Box<dyn BufRead>,
+        /// As written in the glitter file, for messages
+        file_name: String,
+    },
+}
+
+enum TemplateToken {
+    /// Byte range of the content
+    Literal(Range<usize>),
+    Block(ProcessingBlock),
+}
+
+/// A processing block as written in the template: `*{!path:format}`
+#[derive(Clone)]
+pub(crate) struct ProcessingBlock {
+    pub(crate) var_path: String,
+    /// Written without escaping
+    pub(crate) raw: bool,
+    /// Number or date format the value is written in
+    pub(crate) format: Option<String>,
+}
+
+impl ProcessingBlock {
+    /// The path as written, including the format: `price:.2`
+    pub(crate) fn unformatted_path(&self) -> Option<String> {
+        let format = self.format.as_ref()?;
+        Some(format!("{}{FORMAT_SEPARATOR}{format}", self.var_path))
+    }
+}
+
+/// Starts the variable path of a block whose value is written without escaping: `*{!body}`
+const RAW_MARKER: char = '!';
+
+/// Separates the variable path from the format of its value: `*{price:.2}`
+const FORMAT_SEPARATOR: char = ':';
+
+enum ScanError {
+    EmptyBlock { start_at: usize },
+    NonTerminatedBlock { start_at: usize },
+}
+
+/// Part of a template, in the order found
+pub(crate) enum TemplateSegment<'t> {
+    Text(&'t [u8]),
+    Block(&'t ProcessingBlock),
+}
+
+impl CompiledTemplate {
+    /// All positions are byte offsets into the content, markers are found as whole
+    /// characters, so any UTF-8 text around them is copied unchanged
+    pub(crate) fn compile(config: &TemplateRendererConfig, content: String) -> Self {
+        let mut tokens = vec![];
+        let mut literal_start = 0;
+        let mut position = 0;
+
+        let error = loop {
+            let Some(marker_at) = content[position..]
+                .find(config.marker)
+                .map(|offset| position + offset)
+            else {
+                Self::literal(&mut tokens, literal_start..content.len());
+                break None;
+            };
+            let after_marker = marker_at + config.marker.len_utf8();
+            let next = content[after_marker..].chars().next();
+
+            let (block, end) = match processing_statement(config, next) {
+                ProcessingStatement::Block => {
+                    let start = after_marker + config.start_block.len_utf8();
+                    match extract_enclosed_var_path(&content, start, config.end_block) {
+                        Ok(block) => block,
+                        Err(error) => {
+                            Self::literal(&mut tokens, literal_start..marker_at);
+                            break Some(error);
+                        }
+                    }
+                }
+                ProcessingStatement::LineQuote => {
+                    let start = after_marker + config.line_quote.len_utf8();
+                    match extract_trailing_var_path(&content, start) {
+                        Ok(line) => line,
+                        Err(error) => {
+                            Self::literal(&mut tokens, literal_start..marker_at);
+                            break Some(error);
+                        }
+                    }
+                }
+                ProcessingStatement::None => {
+                    position = after_marker;
+                    continue;
+                }
+            };
+
+            Self::literal(&mut tokens, literal_start..marker_at);
+            tokens.push(block);
+            position = end;
+            literal_start = end;
+        };
+
+        CompiledTemplate {
+            content,
+            tokens,
+            error,
+            offset: 0,
+        }
+    }
+
+    fn literal(tokens: &mut Vec<TemplateToken>, range: Range<usize>) {
+        if !range.is_empty() {
+            tokens.push(TemplateToken::Literal(range));
+        }
+    }
+
+    /// A processing block is still open at the end of the content
+    fn is_unterminated(&self) -> bool {
+        matches!(self.error, Some(ScanError::NonTerminatedBlock { .. }))
+    }
+
+    /// Hands every segment to `on_segment` in order, stops at the first error
+    pub(crate) fn for_each_segment(
+        &self,
+        mut on_segment: impl FnMut(TemplateSegment) -> Result<(), TemplateRenderError>,
+    ) -> Result<(), TemplateRenderError> {
+        for token in &self.tokens {
+            match token {
+                TemplateToken::Literal(range) => on_segment(TemplateSegment::Text(
+                    &self.content.as_bytes()[range.clone()],
+                ))?,
+                TemplateToken::Block(block) => on_segment(TemplateSegment::Block(block))?,
+            }
+        }
+
+        match self.error {
+            None => Ok(()),
+            Some(ScanError::EmptyBlock { start_at }) => EmptyProcessingBlockSnafu {
+                start_at: self.offset + start_at,
+            }
+            .fail(),
+            Some(ScanError::NonTerminatedBlock { start_at }) => NonTerminatedProcessingBlockSnafu {
+                start_at: self.offset + start_at,
+            }
+            .fail(),
+        }
+    }
+}
+
+impl LoadedTemplate {
+    /// Hands every segment to `on_segment` in order, stops at the first error
+    pub(crate) fn for_each_segment(
+        self,
+        config: &TemplateRendererConfig,
+        mut on_segment: impl FnMut(TemplateSegment) -> Result<(), TemplateRenderError>,
+    ) -> Result<(), TemplateRenderError> {
+        let (mut reader, file_name) = match self {
+            LoadedTemplate::Compiled(compiled) => return compiled.for_each_segment(on_segment),
+            LoadedTemplate::Streamed { reader, file_name } => (reader, file_name),
+        };
+
+        let mut offset = 0;
+        let mut pending = vec![];
+        loop {
+            let read =
+                reader
+                    .read_until(b'\n', &mut pending)
+                    .context(InvalidTemplateFileSnafu {
+                        file_name: &file_name,
+                    })?;
+            if pending.is_empty() {
+                return Ok(());
+            }
+
+            let content = String::from_utf8(pending)
+                .map_err(|error| std::io::Error::new(ErrorKind::InvalidData, error))
+                .context(InvalidTemplateFileSnafu {
+                    file_name: &file_name,
+                })?;
+            let mut fragment = CompiledTemplate::compile(config, content);
+
+            // blocks may span several lines
+            if read > 0 && fragment.is_unterminated() {
+                pending = fragment.content.into_bytes();
+                continue;
+            }
+
+            fragment.offset = offset;
+            fragment.for_each_segment(&mut on_segment)?;
+            offset += fragment.content.len();
+            pending = fragment.content.into_bytes();
+            pending.clear();
+        }
+    }
+}
+
+/// Compiled templates of one run, inline templates by their text and template files by
+/// their path. Each file is read only once.
+#[derive(Default)]
+pub(crate) struct TemplateCache {
+    texts: Mutex<HashMap<String, Arc<CompiledTemplate>>>,
+    files: Mutex<HashMap<String, Arc<CompiledTemplate>>>,
+}
+
+impl TemplateCache {
+    pub(crate) fn text(
+        &self,
+        config: &TemplateRendererConfig,
+        text: &str,
+    ) -> Arc<CompiledTemplate> {
+        if let Some(compiled) = lock(&self.texts).get(text) {
+            return Arc::clone(compiled);
+        }
+
+        let compiled = Arc::new(CompiledTemplate::compile(config, text.to_owned()));
+        Arc::clone(lock(&self.texts).entry(text.to_owned()).or_insert(compiled))
+    }
+
+    /// Files larger than `CACHED_TEMPLATE_SIZE` are streamed instead. They are read without
+    /// holding the lock, so other threads aren't blocked meanwhile.
+    pub(crate) fn file(
+        &self,
+        config: &TemplateRendererConfig,
+        file_name: &str,
+        full_path: &str,
+        open: impl FnOnce() -> std::io::Result<Box<dyn BufRead>>,
+    ) -> std::io::Result<LoadedTemplate> {
+        if let Some(compiled) = lock(&self.files).get(full_path) {
+            return Ok(LoadedTemplate::Compiled(Arc::clone(compiled)));
+        }
+
+        let mut reader = open()?;
+        let mut beginning = vec![];
+        (&mut reader)
+            .take(CACHED_TEMPLATE_SIZE + 1)
+            .read_to_end(&mut beginning)?;
+
+        if beginning.len() as u64 > CACHED_TEMPLATE_SIZE {
+            return Ok(LoadedTemplate::Streamed {
+                reader: Box::new(Cursor::new(beginning).chain(reader)),
+                file_name: file_name.to_owned(),
+            });
+        }
+
+        let content = String::from_utf8(beginning)
+            .map_err(|error| std::io::Error::new(ErrorKind::InvalidData, error))?;
+        let compiled = Arc::new(CompiledTemplate::compile(config, content));
+
+        Ok(LoadedTemplate::Compiled(Arc::clone(
+            lock(&self.files)
+                .entry(full_path.to_owned())
+                .or_insert(compiled),
+        )))
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// What the character following a marker starts, a marker at the end is plain text
+fn processing_statement(
+    config: &TemplateRendererConfig,
+    next: Option<char>,
+) -> ProcessingStatement {
+    match next {
+        Some(c) if c == config.start_block => ProcessingStatement::Block,
+        Some(c) if c == config.line_quote => ProcessingStatement::LineQuote,
+        _ => ProcessingStatement::None,
+    }
+}
+
+/// The block up to the end marker and the position behind the end marker
+fn extract_enclosed_var_path(
+    template: &str,
+    start_position: usize,
+    end_marker: char,
+) -> Result<(TemplateToken, usize), ScanError> {
+    match template[start_position..].find(end_marker) {
+        Some(length) => Ok((
+            block_token(template, start_position, start_position + length)?,
+            start_position + length + end_marker.len_utf8(),
+        )),
+        None => Err(ScanError::NonTerminatedBlock {
+            start_at: start_position,
+        }),
+    }
+}
+
+/// The block up to the end of the line and the position of the line break
+fn extract_trailing_var_path(
+    template: &str,
+    start_position: usize,
+) -> Result<(TemplateToken, usize), ScanError> {
+    let end = template[start_position..]
+        .find('\n')
+        .map_or(template.len(), |length| start_position + length);
+
+    Ok((block_token(template, start_position, end)?, end))
+}
+
+fn block_token(
+    template: &str,
+    start_position: usize,
+    end: usize,
+) -> Result<TemplateToken, ScanError> {
+    let content = template[start_position..end].trim();
+    let (content, raw) = match content.strip_prefix(RAW_MARKER) {
+        Some(content) => (content.trim_start(), true),
+        None => (content, false),
+    };
+    let (var_path, format) = split_format(content);
+
+    match var_path {
+        "" => Err(ScanError::EmptyBlock {
+            start_at: start_position,
+        }),
+        var_path => Ok(TemplateToken::Block(ProcessingBlock {
+            var_path: var_path.to_owned(),
+            raw,
+            format: format.map(str::to_owned),
+        })),
+    }
+}
+
+/// Only a plain variable path may be followed by a format, so macro calls with a `:` in
+/// their arguments stay as they are, as do paths like `a:b` where `b` is no format
+fn split_format(content: &str) -> (&str, Option<&str>) {
+    match content.split_once(FORMAT_SEPARATOR) {
+        Some((var_path, format))
+            if !var_path
+                .contains(|c: char| c.is_whitespace() || c == '(' || c == '"' || c == '\'') =>
+        {
+            match format {
+                "" => (var_path, None),
+                format if is_format(format) => (var_path, Some(format)),
+                _ => (content, None),
+            }
+        }
+        _ => (content, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+    use crate::rendering::template_rendering::DEFAULT_RENDERER_CONFIG;
+
+    #[derive(Debug, PartialEq)]
+    enum Segment {
+        Text(String),
+        Block(String),
+    }
+
+    /// Segments of the template with adjacent text joined, and whether scanning failed
+    fn segments(template: &str) -> (Vec<Segment>, bool) {
+        let compiled = CompiledTemplate::compile(&DEFAULT_RENDERER_CONFIG, template.to_owned());
+        let mut segments = vec![];
+        let result = compiled.for_each_segment(|segment| {
+            match (segment, segments.last_mut()) {
+                (TemplateSegment::Text(text), Some(Segment::Text(previous))) => {
+                    previous.push_str(std::str::from_utf8(text).unwrap())
+                }
+                (TemplateSegment::Text(text), _) => {
+                    segments.push(Segment::Text(String::from_utf8(text.to_vec()).unwrap()))
+                }
+                (TemplateSegment::Block(block), _) => {
+                    segments.push(Segment::Block(block.var_path.clone()))
+                }
+            }
+            Ok(())
+        });
+
+        (segments, result.is_err())
+    }
+
+    fn text(text: &str) -> Vec<Segment> {
+        match text {
+            "" => vec![],
+            text => vec![Segment::Text(text.to_owned())],
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn any_template_compiles_into_ordered_literals(
+            template in "([*{}>!: a-zä€😀\n]|.){0,64}"
+        ) {
+            let compiled =
+                CompiledTemplate::compile(&DEFAULT_RENDERER_CONFIG, template.clone());
+
+            let mut end = 0;
+            for token in &compiled.tokens {
+                if let TemplateToken::Literal(range) = token {
+                    prop_assert!(range.start >= end);
+                    prop_assert!(template.get(range.clone()).is_some());
+                    end = range.end;
+                }
+            }
+            segments(&template);
+        }
+
+        #[test]
+        fn text_without_blocks_round_trips(template in "([^*]|\\*[^*{>])*\\*?") {
+            prop_assert_eq!(segments(&template), (text(&template), false));
+        }
+
+        #[test]
+        fn literals_around_a_block_round_trip(
+            before in "[^*]*",
+            name in "[a-z][a-z0-9_]{0,8}",
+            after in "[^*]*",
+        ) {
+            let (found, failed) = segments(&format!("{before}*{{{name}}}{after}"));
+
+            let mut expected = text(&before);
+            expected.push(Segment::Block(name));
+            expected.extend(text(&after));
+            prop_assert_eq!(found, expected);
+            prop_assert!(!failed);
+        }
+
+        #[test]
+        fn literals_around_a_line_quote_round_trip(
+            before in "[^*]*",
+            name in "[a-z][a-z0-9_]{0,8}",
+            after in "[^*]*",
+        ) {
+            let (found, failed) = segments(&format!("{before}*> {name}\n{after}"));
+
+            let mut expected = text(&before);
+            expected.push(Segment::Block(name));
+            expected.extend(text(&format!("\n{after}")));
+            prop_assert_eq!(found, expected);
+            prop_assert!(!failed);
+        }
+
+        #[test]
+        fn open_block_keeps_the_text_in_front(before in "[^*]*", rest in "[^*}]*") {
+            prop_assert_eq!(segments(&format!("{before}*{{{rest}")), (text(&before), true));
+        }
+    }
+}
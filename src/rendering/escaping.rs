@@ -0,0 +1,65 @@
+use std::borrow::Cow;
+use std::io::Write;
+
+use crate::config::model::EscapeMode;
+
+/// Escapes everything written through it. Only ASCII characters are replaced, so text may
+/// be split anywhere between writes, also within a UTF-8 character.
+pub(crate) struct EscapingWriter<'w> {
+    inner: &'w mut dyn Write,
+    escape: EscapeMode,
+}
+
+impl<'w> EscapingWriter<'w> {
+    pub(crate) fn new(inner: &'w mut dyn Write, escape: EscapeMode) -> Self {
+        EscapingWriter { inner, escape }
+    }
+}
+
+impl Write for EscapingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let mut unescaped_from = 0;
+        for (position, byte) in buf.iter().enumerate() {
+            if let Some(replacement) = escaped(self.escape, *byte) {
+                self.inner.write_all(&buf[unescaped_from..position])?;
+                self.inner.write_all(&replacement)?;
+                unescaped_from = position + 1;
+            }
+        }
+        self.inner.write_all(&buf[unescaped_from..])?;
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// The replacement of a byte, `None` if it is written as it is
+fn escaped(escape: EscapeMode, byte: u8) -> Option<Cow<'static, [u8]>> {
+    let replacement: &'static [u8] = match (escape, byte) {
+        (EscapeMode::Html | EscapeMode::Xml, b'&') => b"&amp;",
+        (EscapeMode::Html | EscapeMode::Xml, b'<') => b"&lt;",
+        (EscapeMode::Html | EscapeMode::Xml, b'>') => b"&gt;",
+        (EscapeMode::Html | EscapeMode::Xml, b'"') => b"&quot;",
+        (EscapeMode::Html, b'\'') => b"&#39;",
+        (EscapeMode::Xml, b'\'') => b"&apos;",
+        (EscapeMode::JsonString, b'"') => b"\\\"",
+        (EscapeMode::JsonString, b'\\') => b"\\\\",
+        (EscapeMode::JsonString, b'\n') => b"\\n",
+        (EscapeMode::JsonString, b'\r') => b"\\r",
+        (EscapeMode::JsonString, b'\t') => b"\\t",
+        (EscapeMode::JsonString, 0x08) => b"\\b",
+        (EscapeMode::JsonString, 0x0C) => b"\\f",
+        (EscapeMode::JsonString, 0x00..=0x1F) => {
+            return Some(Cow::Owned(format!("\\u{:04x}", byte).into_bytes()))
+        }
+        (EscapeMode::ShellSingleQuote, b'\'') => b"'\\''",
+        (EscapeMode::Csv, b'"') => b"\"\"",
+        (EscapeMode::SqlLiteral, b'\'') => b"''",
+        _ => return None,
+    };
+
+    Some(Cow::Borrowed(replacement))
+}
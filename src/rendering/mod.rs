@@ -4,9 +4,16 @@ use std::string::FromUtf8Error;
 use snafu::Snafu;
 
 use crate::config::reader::ConfigReadError;
+use crate::config::yaml_import::YamlImportReadError;
+use crate::processing::AccessViolation;
 use crate::rendering::template_rendering::TemplateRenderer;
 
+pub(crate) mod compiled_template;
+pub(crate) mod escaping;
+pub mod output_files;
 pub mod template_rendering;
+pub mod text_encoding;
+pub mod value_format;
 pub(crate) mod var_rendering;
 
 enum ProcessingStatement {
@@ -44,6 +51,54 @@ pub enum TemplateRenderError {
         source_definition: String,
         backtrace: Backtrace,
     },
+    #[snafu(display("Failed to write output file {}", file_name))]
+    #[snafu(visibility(pub(crate)))]
+    OutputFile {
+        file_name: String,
+        #[snafu(source(from(std::io::Error, Box::new)))]
+        source: Box<std::io::Error>,
+        backtrace: Backtrace,
+    },
+    #[snafu(display(
+        "Output file name rendered from {} is not valid UTF-8",
+        file_name_pattern
+    ))]
+    InvalidOutputFileName {
+        file_name_pattern: String,
+        source: FromUtf8Error,
+        backtrace: Backtrace,
+    },
+    #[snafu(display(
+        "Output file name {:?} rendered from {} {}",
+        file_name,
+        file_name_pattern,
+        reason
+    ))]
+    InvalidIterationFile {
+        file_name: String,
+        file_name_pattern: String,
+        reason: &'static str,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Access to template file denied by policy"))]
+    TemplateAccessDenied {
+        #[snafu(backtrace)]
+        source: AccessViolation,
+    },
+    #[snafu(display("Failed to render layout {}", file_name))]
+    #[snafu(visibility(pub(crate)))]
+    LayoutFailed {
+        file_name: String,
+        #[snafu(backtrace)]
+        #[snafu(source(from(ValueRenderError, Box::new)))]
+        source: Box<ValueRenderError>,
+    },
+    #[snafu(display("Rendered content for a layout is not valid UTF-8"))]
+    #[snafu(visibility(pub(crate)))]
+    InvalidLayoutContent {
+        source: FromUtf8Error,
+        backtrace: Backtrace,
+    },
     #[snafu(display("Invalid template source file {}", file_name))]
     InvalidTemplateFile {
         file_name: String,
@@ -70,9 +125,18 @@ pub enum ValueRenderError {
         source: Box<std::io::Error>,
         backtrace: Backtrace,
     },
-    #[snafu(display("Value can't be calculated, only rendered directly"))]
-    InvalidCalculateCall { backtrace: Backtrace },
-
+    #[snafu(display("Failed to write output file {}", file_name))]
+    FailedWritingOutputFile {
+        file_name: String,
+        #[snafu(source(from(std::io::Error, Box::new)))]
+        source: Box<std::io::Error>,
+        backtrace: Backtrace,
+    },
+    #[snafu(display("Access denied by policy"))]
+    AccessDenied {
+        #[snafu(backtrace)]
+        source: AccessViolation,
+    },
     #[snafu(display("Sub-Rendering failed"))]
     RenderCommandFailed {
         #[snafu(backtrace)]
@@ -80,6 +144,13 @@ pub enum ValueRenderError {
         source: Box<TemplateRenderError>,
     },
 
+    #[snafu(display("Sub-Rendering produced invalid UTF-8"))]
+    RenderResultInvalid {
+        #[snafu(source(from(FromUtf8Error, Box::new)))]
+        source: Box<FromUtf8Error>,
+        backtrace: Backtrace,
+    },
+
     #[snafu(display("Filesystem could not be accessed successfully"))]
     FailedAccessingFilesystem { backtrace: Backtrace },
 
@@ -90,12 +161,56 @@ pub enum ValueRenderError {
         source: Box<ConfigReadError>,
     },
 
+    #[snafu(display("Failed to process import"))]
+    ImportCommandFailed {
+        #[snafu(backtrace)]
+        #[snafu(source(from(YamlImportReadError, Box::new)))]
+        source: Box<YamlImportReadError>,
+    },
+
     ExecuteCommandFailed {
         #[snafu(source(from(std::io::Error, Box::new)))]
         source: Box<std::io::Error>,
         backtrace: Backtrace,
     },
 
+    #[snafu(display(
+        "Executable {} found neither next to the glitter file nor in PATH",
+        executable
+    ))]
+    ExecutableNotFound {
+        executable: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Command {} did not finish within {:?}", executable, timeout))]
+    ExecuteTimedOut {
+        executable: String,
+        timeout: std::time::Duration,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display(
+        "Command {} failed with {}{}",
+        executable,
+        exit_code.map_or_else(|| String::from("no exit code (killed by a signal)"), |code| format!("exit code {}", code)),
+        if stderr_tail.is_empty() { String::new() } else { format!(":\n{}", stderr_tail) }
+    ))]
+    ExecuteExitedWithFailure {
+        executable: String,
+        exit_code: Option<i32>,
+        stderr_tail: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Output of command {} could not be parsed", executable))]
+    ExecuteResultUnparsable {
+        executable: String,
+        #[snafu(backtrace)]
+        #[snafu(source(from(YamlImportReadError, Box::new)))]
+        source: Box<YamlImportReadError>,
+    },
+
     ExecuteResultInvalid {
         #[snafu(source(from(FromUtf8Error, Box::new)))]
         source: Box<FromUtf8Error>,
@@ -128,6 +243,76 @@ pub enum ValueRenderError {
         #[snafu(source(from(ConfigReadError, Box::new)))]
         source: Box<ConfigReadError>,
     },
+
+    #[snafu(display(
+        "Sub-render {} has `outputs`, only `output` of the render statement writes a file",
+        render
+    ))]
+    SubRenderWithOutputs {
+        render: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Invalid macro call: {}", call))]
+    InvalidMacroCall { call: String, backtrace: Backtrace },
+
+    #[snafu(display(
+        "Layout {} extends itself, directly or through other layouts",
+        file_name
+    ))]
+    LayoutCycle {
+        file_name: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("{} is not a macro", name))]
+    NotAMacro { name: String, backtrace: Backtrace },
+
+    #[snafu(display("Macro {} takes {} arguments, {} given", name, expected, given))]
+    MacroArgumentCount {
+        name: String,
+        expected: usize,
+        given: usize,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Macro {} has no argument {}", name, argument))]
+    UnknownMacroArgument {
+        name: String,
+        argument: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Missing argument {} for macro {}", argument, name))]
+    MissingMacroArgument {
+        name: String,
+        argument: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Value {:?} can't be formatted with {}: {}", value, format, reason))]
+    InvalidFormat {
+        value: String,
+        format: String,
+        reason: String,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Value formatted with {} is not valid UTF-8", format))]
+    FormattedValueInvalid {
+        format: String,
+        #[snafu(source(from(FromUtf8Error, Box::new)))]
+        source: Box<FromUtf8Error>,
+        backtrace: Backtrace,
+    },
+
+    #[snafu(display("Failed to expand macro {}", name))]
+    MacroFailed {
+        name: String,
+        #[snafu(backtrace)]
+        #[snafu(source(from(TemplateRenderError, Box::new)))]
+        source: Box<TemplateRenderError>,
+    },
 }
 
 pub trait ValueRenderer {
@@ -137,4 +322,21 @@ pub trait ValueRenderer {
         iteration_count: usize,
         output: &mut TemplateRenderer,
     ) -> Result<(), ValueRenderError>;
+
+    /// Whether the value is a `render`, `execute` or `load`, which may be evaluated
+    /// concurrently to the rest of the template
+    fn is_independent(&self, _variable_path: &str, _iteration_count: usize) -> bool {
+        false
+    }
+
+    /// Whether the block writes template text, like a macro call, which is not escaped
+    fn is_markup(&self, _variable_path: &str) -> bool {
+        false
+    }
+
+    /// Whether the variable is known to exist, so `*{a:8}` can name the key `a:8` rather
+    /// than `a` in the format `8`
+    fn has_variable(&self, _variable_path: &str, _iteration_count: usize) -> bool {
+        false
+    }
 }
@@ -0,0 +1,127 @@
+use std::fs::File;
+use std::io::{BufWriter, ErrorKind, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+
+/// Destination for all files written besides the main output.
+/// Iterations rendered on several threads create their files through the same instance.
+pub trait OutputFiles: Send {
+    /// Creates (or truncates) the file at the given path relative to the output root
+    fn create(&mut self, path: &str) -> std::io::Result<Box<dyn Write>>;
+
+    /// All files created so far, in order of their first creation
+    fn written_files(&self) -> Vec<String>;
+}
+
+impl<T: OutputFiles + ?Sized> OutputFiles for &mut T {
+    fn create(&mut self, path: &str) -> std::io::Result<Box<dyn Write>> {
+        (**self).create(path)
+    }
+
+    fn written_files(&self) -> Vec<String> {
+        (**self).written_files()
+    }
+}
+
+impl<T: OutputFiles + ?Sized> OutputFiles for Box<T> {
+    fn create(&mut self, path: &str) -> std::io::Result<Box<dyn Write>> {
+        (**self).create(path)
+    }
+
+    fn written_files(&self) -> Vec<String> {
+        (**self).written_files()
+    }
+}
+
+impl<T: OutputFiles> OutputFiles for Option<T> {
+    fn create(&mut self, path: &str) -> std::io::Result<Box<dyn Write>> {
+        match self {
+            Some(files) => files.create(path),
+            None => Err(std::io::Error::other(
+                "Writing additional output files is not enabled",
+            )),
+        }
+    }
+
+    fn written_files(&self) -> Vec<String> {
+        self.as_ref()
+            .map(OutputFiles::written_files)
+            .unwrap_or_default()
+    }
+}
+
+/// Creates files one at a time, so renders on several threads can share the output files
+pub(crate) struct SharedOutputFiles<'a, F: OutputFiles>(pub(crate) &'a Mutex<F>);
+
+impl<F: OutputFiles> OutputFiles for SharedOutputFiles<'_, F> {
+    fn create(&mut self, path: &str) -> std::io::Result<Box<dyn Write>> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .create(path)
+    }
+
+    fn written_files(&self) -> Vec<String> {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .written_files()
+    }
+}
+
+/// Whether a path relative to the output root stays within it: it is neither absolute nor
+/// contains `..`
+pub(crate) fn is_within_root(path: &str) -> bool {
+    !Path::new(path).components().any(|component| {
+        matches!(
+            component,
+            Component::Prefix(_) | Component::RootDir | Component::ParentDir
+        )
+    })
+}
+
+/// Writes all output files into a directory on disk. Paths leaving it (absolute ones or
+/// those containing `..`) are refused.
+pub struct DirectoryOutput {
+    root: PathBuf,
+    written: Vec<PathBuf>,
+}
+
+impl DirectoryOutput {
+    pub fn new<P: AsRef<Path>>(root: P) -> Self {
+        DirectoryOutput {
+            root: root.as_ref().to_path_buf(),
+            written: vec![],
+        }
+    }
+}
+
+impl OutputFiles for DirectoryOutput {
+    fn create(&mut self, path: &str) -> std::io::Result<Box<dyn Write>> {
+        if !is_within_root(path) {
+            return Err(std::io::Error::new(
+                ErrorKind::PermissionDenied,
+                format!("{} is outside of the output root", path),
+            ));
+        }
+
+        let full_path = self.root.join(path);
+        if let Some(parent) = full_path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        let file = File::create(&full_path)?;
+        if !self.written.contains(&full_path) {
+            self.written.push(full_path);
+        }
+
+        Ok(Box::new(BufWriter::new(file)))
+    }
+
+    fn written_files(&self) -> Vec<String> {
+        self.written
+            .iter()
+            .map(|path| path.to_string_lossy().into_owned())
+            .collect()
+    }
+}
@@ -1,16 +1,30 @@
+use crate::rendering::compiled_template::{
+    LoadedTemplate, ProcessingBlock, TemplateCache, TemplateSegment,
+};
+use crate::rendering::escaping::EscapingWriter;
+use crate::rendering::output_files::{is_within_root, OutputFiles, SharedOutputFiles};
+use crate::rendering::text_encoding::EncodedWriter;
+use crate::rendering::value_format::format_value;
 use crate::{
-    config::model::{TemplateDefinition, TemplateValue},
-    processing::ProcessingContext,
+    config::model::{EscapeMode, OutputFormat, TemplateDefinition, TemplateValue},
+    processing::{AccessPolicy, ProcessingContext},
     rendering::{
-        EmptyProcessingBlockSnafu, InvalidTemplateFileSnafu, NonTerminatedProcessingBlockSnafu,
-        OutputWriteSnafu, ProcessingStatement, TemplateRenderError, ValueRenderer,
+        FailedWritingTextSnafu, FormattedValueInvalidSnafu, InvalidIterationFileSnafu,
+        InvalidOutputFileNameSnafu, InvalidTemplateFileSnafu, OutputFileSnafu, OutputWriteSnafu,
+        TemplateAccessDeniedSnafu, TemplateRenderError, ValueRenderError, ValueRenderer,
         ValueRenderingFailedSnafu,
     },
 };
 use snafu::ResultExt;
-use std::io::{BufReader, Read, Write};
-use std::rc::Rc;
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::io::{ErrorKind, Write};
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::{Scope, ScopedJoinHandle};
 
+#[derive(Clone)]
 pub struct TemplateRendererConfig {
     pub marker: char,
     pub start_block: char,
@@ -28,6 +42,35 @@ pub const DEFAULT_RENDERER_CONFIG: TemplateRendererConfig = TemplateRendererConf
 pub struct TemplateRenderer<'a> {
     config: TemplateRendererConfig,
     output: &'a mut dyn Write,
+    files: Option<&'a mut dyn OutputFiles>,
+    jobs: Option<Arc<JobSlots>>,
+    templates: Arc<TemplateCache>,
+    /// Files of routed sub-renders written in this run
+    routed_files: Arc<Mutex<HashSet<String>>>,
+    /// Of created files which don't set their own
+    output_format: OutputFormat,
+    /// Of the values written by the template currently rendered
+    escape: EscapeMode,
+}
+
+/// Threads which may still be started to render values concurrently, shared by all
+/// renderers of one run so nested sub-renders respect the same limit
+pub(crate) struct JobSlots {
+    available: AtomicUsize,
+}
+
+impl JobSlots {
+    fn try_acquire(&self) -> bool {
+        self.available
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |available| {
+                available.checked_sub(1)
+            })
+            .is_ok()
+    }
+
+    fn release(&self) {
+        self.available.fetch_add(1, Ordering::AcqRel);
+    }
 }
 
 impl<'a> TemplateRenderer<'a> {
@@ -35,206 +78,472 @@ impl<'a> TemplateRenderer<'a> {
         Self {
             config: DEFAULT_RENDERER_CONFIG,
             output,
+            files: None,
+            jobs: None,
+            templates: Arc::default(),
+            routed_files: Arc::default(),
+            output_format: OutputFormat::default(),
+            escape: EscapeMode::None,
         }
     }
 
     pub fn based_upon_config(config: TemplateRendererConfig, output: &'a mut dyn Write) -> Self {
-        Self { config, output }
+        Self {
+            config,
+            output,
+            files: None,
+            jobs: None,
+            templates: Arc::default(),
+            routed_files: Arc::default(),
+            output_format: OutputFormat::default(),
+            escape: EscapeMode::None,
+        }
+    }
+
+    /// Enables writing additional files (`outputs`, sub-renders with `output`)
+    pub fn with_output_files(mut self, files: &'a mut dyn OutputFiles) -> Self {
+        self.files = Some(files);
+        self
+    }
+
+    /// Evaluates up to `jobs` independent values (`render`, `execute`, `load`) at the same
+    /// time, each into its own buffer. The buffers are written in template order, so the
+    /// output is the same as with one job.
+    pub fn with_jobs(mut self, jobs: usize) -> Self {
+        self.jobs = (jobs > 1).then(|| {
+            Arc::new(JobSlots {
+                available: AtomicUsize::new(jobs - 1),
+            })
+        });
+        self
+    }
+
+    /// Newlines, encoding and BOM of created files which don't set their own
+    pub fn with_output_format(mut self, output_format: OutputFormat) -> Self {
+        self.output_format = output_format;
+        self
+    }
+
+    /// A renderer with the same configuration, but writing into another output
+    pub(crate) fn redirect<'b>(&'b mut self, output: &'b mut dyn Write) -> TemplateRenderer<'b> {
+        TemplateRenderer {
+            config: self.config.clone(),
+            output,
+            files: match &mut self.files {
+                Some(files) => Some(&mut **files),
+                None => None,
+            },
+            jobs: self.jobs.clone(),
+            templates: Arc::clone(&self.templates),
+            routed_files: Arc::clone(&self.routed_files),
+            output_format: self.output_format,
+            escape: self.escape,
+        }
+    }
+
+    /// A renderer writing into the same output, creating files in `output_format` as far as
+    /// it is set
+    pub(crate) fn formatted(&mut self, output_format: &OutputFormat) -> TemplateRenderer<'_> {
+        TemplateRenderer {
+            config: self.config.clone(),
+            output: &mut *self.output,
+            files: match &mut self.files {
+                Some(files) => Some(&mut **files),
+                None => None,
+            },
+            jobs: self.jobs.clone(),
+            templates: Arc::clone(&self.templates),
+            routed_files: Arc::clone(&self.routed_files),
+            output_format: output_format.or(&self.output_format),
+            escape: self.escape,
+        }
+    }
+
+    /// The file is written in `output_format`, options not set there in the renderer's format.
+    /// Its path is checked against the policy of the config naming it.
+    pub(crate) fn create_output_file(
+        &mut self,
+        path: &str,
+        output_format: &OutputFormat,
+        policy: &AccessPolicy,
+    ) -> std::io::Result<Box<dyn Write>> {
+        policy
+            .check_output_path(path)
+            .map_err(|violation| std::io::Error::new(ErrorKind::PermissionDenied, violation))?;
+        let file = self.files.create(path)?;
+
+        Ok(Box::new(EncodedWriter::new(
+            file,
+            &output_format.or(&self.output_format),
+        )))
+    }
+
+    /// Whether the file of a routed sub-render is still to be written in this run. It is
+    /// written by the first render referencing it, all later ones only name it.
+    pub(crate) fn claim_routed_file(&self, path: &str) -> bool {
+        self.routed_files
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(path.to_owned())
+    }
+
+    /// Values are escaped as the template says, while it is rendered
+    pub(crate) fn render<TValueRenderer: ValueRenderer + Sync>(
+        &mut self,
+        template: &TemplateDefinition,
+        body_iterations: usize,
+        context: Arc<ProcessingContext>,
+        value_renderer: &TValueRenderer,
+    ) -> Result<(), TemplateRenderError> {
+        let escape = std::mem::replace(&mut self.escape, template.escape.unwrap_or_default());
+        let result = self.render_sections(template, body_iterations, context, value_renderer);
+        self.escape = escape;
+
+        result
     }
 
-    pub(crate) fn render<TValueRenderer: ValueRenderer>(
+    fn render_sections<TValueRenderer: ValueRenderer + Sync>(
         &mut self,
         template: &TemplateDefinition,
         body_iterations: usize,
-        context: Rc<ProcessingContext>,
+        context: Arc<ProcessingContext>,
         value_renderer: &TValueRenderer,
     ) -> Result<(), TemplateRenderError> {
-        if let Some(header) = &template.header {
-            self.heavylift_render(header, 0, &context, value_renderer)?;
+        let split_output = template.output_per_iteration.is_some();
+
+        if !(split_output && template.repeat_header_footer) {
+            if let Some(header) = &template.header {
+                self.heavylift_render(header, 0, &context, value_renderer)?;
+            }
         }
 
+        if let (Some(jobs), None) = (self.jobs.clone(), &template.output_per_iteration) {
+            self.render_concurrently(
+                &template.body,
+                0..body_iterations,
+                true,
+                context.clone(),
+                value_renderer,
+                jobs,
+            )?;
+            return self.render_footer(template, split_output, &context, value_renderer);
+        }
+
+        let mut iteration_files = HashSet::new();
         for iteration_count in 0..body_iterations {
+            if let Some(file_name_pattern) = &template.output_per_iteration {
+                self.render_iteration_file(
+                    template,
+                    file_name_pattern,
+                    &mut iteration_files,
+                    iteration_count,
+                    &context,
+                    value_renderer,
+                )?;
+                continue;
+            }
+
             self.heavylift_render(&template.body, iteration_count, &context, value_renderer)?;
-            self.output.write(b"\n").context(OutputWriteSnafu)?;
+            self.output.write_all(b"\n").context(OutputWriteSnafu)?;
         }
 
-        if let Some(footer) = &template.footer {
-            self.heavylift_render(footer, 0, &context, value_renderer)?;
+        self.render_footer(template, split_output, &context, value_renderer)
+    }
+
+    fn render_footer<TValueRenderer: ValueRenderer + Sync>(
+        &mut self,
+        template: &TemplateDefinition,
+        split_output: bool,
+        context: &Arc<ProcessingContext>,
+        value_renderer: &TValueRenderer,
+    ) -> Result<(), TemplateRenderError> {
+        if !(split_output && template.repeat_header_footer) {
+            if let Some(footer) = &template.footer {
+                self.heavylift_render(footer, 0, context, value_renderer)?;
+            }
         }
 
         Ok(())
     }
 
-    fn heavylift_render<TValueRenderer: ValueRenderer>(
+    /// Renders the template once per iteration, with `render`, `execute` and `load` values
+    /// evaluated on other threads while job slots are free. All parts are written in template
+    /// order afterwards, up to the first error.
+    fn render_concurrently<TValueRenderer: ValueRenderer + Sync>(
         &mut self,
         template: &TemplateValue,
-        iteration_count: usize,
-        context: &Rc<ProcessingContext>,
+        iterations: Range<usize>,
+        line_breaks: bool,
+        context: Arc<ProcessingContext>,
         value_renderer: &TValueRenderer,
+        jobs: Arc<JobSlots>,
     ) -> Result<(), TemplateRenderError> {
-        let mut from: usize = 0;
-        let mut to: Option<usize> = None;
-
-        let template_content = self.read_template(template, context)?;
-
-        let chars = template_content.chars().collect::<Vec<_>>();
-        let mut char_index = 0;
-
-        while char_index < template_content.len() {
-            if chars[char_index] == self.config.marker {
-                match self.is_processing_block(&chars, char_index) {
-                    ProcessingStatement::Block => {
-                        self.quote(&template_content, from, to)?;
-
-                        let (var_path, block_length) = self.extract_enclosed_var_path(
-                            &template_content,
-                            &chars,
-                            char_index + 2,
-                            self.config.end_block,
-                        )?;
-                        value_renderer
-                            .render_value(&var_path, iteration_count, self)
-                            .context(ValueRenderingFailedSnafu)?;
-
-                        char_index += 3 + block_length;
-                        from = char_index;
-                        to = None;
-                    }
-                    ProcessingStatement::LineQuote => {
-                        self.quote(&template_content, from, to)?;
-
-                        let (var_path, line_length) = self.extract_trailing_var_path(
-                            &template_content,
-                            &chars,
-                            char_index + 2,
-                        )?;
-                        value_renderer
-                            .render_value(&var_path, iteration_count, self)
-                            .context(ValueRenderingFailedSnafu)?;
-
-                        char_index += 2 + line_length;
-                        from = char_index;
-                        to = None;
-                    }
-                    ProcessingStatement::None => {
-                        char_index += 1;
-                    }
+        let files = Mutex::new(self.files.take());
+        let concurrent_render = ConcurrentRender {
+            config: self.config.clone(),
+            files: &files,
+            jobs,
+            templates: Arc::clone(&self.templates),
+            routed_files: Arc::clone(&self.routed_files),
+            output_format: self.output_format,
+            escape: self.escape,
+            context,
+            value_renderer,
+        };
+
+        let rendered = std::thread::scope(|scope| {
+            let mut parts = vec![];
+            for iteration_count in iterations {
+                let result = load_template(
+                    &concurrent_render.templates,
+                    &concurrent_render.config,
+                    template,
+                    &concurrent_render.context,
+                )
+                .and_then(|loaded| {
+                    concurrent_render.collect(scope, loaded, iteration_count, &mut parts)
+                });
+                if let Err(error) = result {
+                    parts.push(RenderedPart::Done(vec![], Err(error)));
+                    break;
+                }
+                if line_breaks {
+                    parts.push(RenderedPart::Done(b"\n".to_vec(), Ok(())));
                 }
-            } else {
-                to = Some(char_index);
-                char_index += 1;
             }
-        }
 
-        self.quote(&template_content, from, to)?;
+            parts
+                .into_iter()
+                .map(RenderedPart::join)
+                .collect::<Vec<_>>()
+        });
+
+        self.files = files.into_inner().unwrap_or_else(PoisonError::into_inner);
+
+        for (buffer, result) in rendered {
+            self.output.write_all(&buffer).context(OutputWriteSnafu)?;
+            result?;
+        }
 
         Ok(())
     }
 
-    fn read_template(
-        &self,
+    /// Renders a single template value once, without the line break added after iterations
+    pub(crate) fn render_fragment<TValueRenderer: ValueRenderer + Sync>(
+        &mut self,
         template: &TemplateValue,
-        context: &Rc<ProcessingContext>,
-    ) -> Result<String, TemplateRenderError> {
-        match template {
-            TemplateValue::RawValue(template) => Ok(template.clone()),
-            TemplateValue::Quote(file_path) => {
-                let full_file_path = context.resolve_filename(file_path);
-                let file =
-                    std::fs::File::open(full_file_path).context(InvalidTemplateFileSnafu {
-                        file_name: file_path,
-                    })?;
-
-                let mut buf_reader = BufReader::new(file);
-                let mut contents = String::new();
-                buf_reader
-                    .read_to_string(&mut contents)
-                    .context(InvalidTemplateFileSnafu {
-                        file_name: file_path,
-                    })?;
-
-                Ok(contents)
-            }
-        }
+        context: Arc<ProcessingContext>,
+        value_renderer: &TValueRenderer,
+    ) -> Result<(), TemplateRenderError> {
+        self.heavylift_render(template, 0, &context, value_renderer)
     }
 
-    fn is_processing_block(&self, chars: &[char], char_index: usize) -> ProcessingStatement {
-        if chars[char_index + 1] == self.config.start_block {
-            ProcessingStatement::Block
-        } else if chars[char_index + 1] == self.config.line_quote {
-            ProcessingStatement::LineQuote
+    /// Writes the body of one iteration into the file named by the rendered pattern. The name
+    /// has to stay within the output root and differ from those of earlier iterations.
+    fn render_iteration_file<TValueRenderer: ValueRenderer + Sync>(
+        &mut self,
+        template: &TemplateDefinition,
+        file_name_pattern: &str,
+        written_files: &mut HashSet<String>,
+        iteration_count: usize,
+        context: &Arc<ProcessingContext>,
+        value_renderer: &TValueRenderer,
+    ) -> Result<(), TemplateRenderError> {
+        // file names are not escaped
+        let mut file_name = Vec::<u8>::new();
+        let mut name_renderer = self.redirect(&mut file_name);
+        name_renderer.escape = EscapeMode::None;
+        name_renderer.heavylift_render(
+            &TemplateValue::RawValue(file_name_pattern.to_owned()),
+            iteration_count,
+            context,
+            value_renderer,
+        )?;
+        let file_name = String::from_utf8(file_name)
+            .context(InvalidOutputFileNameSnafu { file_name_pattern })?;
+        let invalid = if file_name.trim().is_empty() {
+            Some("is empty")
+        } else if !is_within_root(&file_name) {
+            Some("is outside of the output root")
+        } else if !written_files.insert(file_name.clone()) {
+            Some("is used by an earlier iteration already")
         } else {
-            ProcessingStatement::None
+            None
+        };
+        if let Some(reason) = invalid {
+            return InvalidIterationFileSnafu {
+                file_name,
+                file_name_pattern,
+                reason,
+            }
+            .fail();
+        }
+
+        let mut file = self
+            .create_output_file(&file_name, &OutputFormat::default(), &context.policy)
+            .context(OutputFileSnafu {
+                file_name: file_name.clone(),
+            })?;
+        {
+            let mut file_renderer = self.redirect(&mut file);
+            if template.repeat_header_footer {
+                if let Some(header) = &template.header {
+                    file_renderer.heavylift_render(
+                        header,
+                        iteration_count,
+                        context,
+                        value_renderer,
+                    )?;
+                }
+            }
+
+            file_renderer.heavylift_render(
+                &template.body,
+                iteration_count,
+                context,
+                value_renderer,
+            )?;
+            file_renderer
+                .output
+                .write_all(b"\n")
+                .context(OutputWriteSnafu)?;
+
+            if template.repeat_header_footer {
+                if let Some(footer) = &template.footer {
+                    file_renderer.heavylift_render(
+                        footer,
+                        iteration_count,
+                        context,
+                        value_renderer,
+                    )?;
+                }
+            }
         }
+
+        file.flush().context(OutputFileSnafu { file_name })
     }
 
-    fn quote(
+    fn heavylift_render<TValueRenderer: ValueRenderer + Sync>(
         &mut self,
-        template: &str,
-        from: usize,
-        to_option: Option<usize>,
+        template: &TemplateValue,
+        iteration_count: usize,
+        context: &Arc<ProcessingContext>,
+        value_renderer: &TValueRenderer,
     ) -> Result<(), TemplateRenderError> {
-        if let Some(to) = to_option {
-            self.output
-                .write(template[from..=to].as_bytes())
-                .context(OutputWriteSnafu)?;
+        if let Some(jobs) = self.jobs.clone() {
+            return self.render_concurrently(
+                template,
+                iteration_count..iteration_count + 1,
+                false,
+                Arc::clone(context),
+                value_renderer,
+                jobs,
+            );
         }
 
-        Ok(())
+        let config = self.config.clone();
+        let loaded = load_template(&self.templates, &config, template, context)?;
+
+        loaded.for_each_segment(&config, |segment| match segment {
+            TemplateSegment::Text(text) => {
+                self.output.write_all(text).context(OutputWriteSnafu)?;
+                Ok(())
+            }
+            TemplateSegment::Block(block) => self
+                .render_block(value_renderer, block, iteration_count)
+                .context(ValueRenderingFailedSnafu),
+        })
     }
 
-    fn extract_enclosed_var_path(
-        &self,
-        template: &str,
-        chars: &[char],
-        start_position: usize,
-        end_marker: char,
-    ) -> Result<(String, usize), TemplateRenderError> {
-        if let Some(relative_index) = chars[start_position..]
-            .iter()
-            .position(|&c| c == end_marker)
+    /// Writes the value of a processing block in its format, escaped unless it is `raw` or
+    /// template text
+    fn render_block<TValueRenderer: ValueRenderer + Sync>(
+        &mut self,
+        value_renderer: &TValueRenderer,
+        block: &ProcessingBlock,
+        iteration_count: usize,
+    ) -> Result<(), ValueRenderError> {
+        let (var_path, format) = block_path(value_renderer, block, iteration_count);
+        let escape = if block.raw
+            || self.escape == EscapeMode::None
+            || value_renderer.is_markup(&var_path)
         {
-            Self::return_variable_name(template, start_position, relative_index)
+            EscapeMode::None
         } else {
-            NonTerminatedProcessingBlockSnafu {
-                start_at: start_position,
+            self.escape
+        };
+
+        let Some(format) = format else {
+            if escape == EscapeMode::None {
+                return value_renderer.render_value(&var_path, iteration_count, self);
             }
-            .fail()
-        }
+            let mut escaped = EscapingWriter::new(&mut *self.output, escape);
+            let mut renderer = TemplateRenderer {
+                config: self.config.clone(),
+                output: &mut escaped,
+                files: match &mut self.files {
+                    Some(files) => Some(&mut **files),
+                    None => None,
+                },
+                jobs: self.jobs.clone(),
+                templates: Arc::clone(&self.templates),
+                routed_files: Arc::clone(&self.routed_files),
+                output_format: self.output_format,
+                escape: EscapeMode::None,
+            };
+            return value_renderer.render_value(&var_path, iteration_count, &mut renderer);
+        };
+
+        let mut rendered = Vec::<u8>::new();
+        let mut renderer = self.redirect(&mut rendered);
+        renderer.escape = EscapeMode::None;
+        value_renderer.render_value(&var_path, iteration_count, &mut renderer)?;
+        let value = String::from_utf8(rendered).context(FormattedValueInvalidSnafu { format })?;
+        let formatted = format_value(&value, format)?;
+
+        EscapingWriter::new(&mut *self.output, escape)
+            .write_all(formatted.as_bytes())
+            .context(FailedWritingTextSnafu)
     }
+}
 
-    fn extract_trailing_var_path(
-        &self,
-        template: &str,
-        chars: &[char],
-        start_position: usize,
-    ) -> Result<(String, usize), TemplateRenderError> {
-        if let Some(relative_index) = chars[start_position..].iter().position(|&c| c == '\n') {
-            Self::return_variable_name(template, start_position, relative_index)
-        } else {
-            Ok((
-                template[start_position..].trim().to_owned(),
-                template.len() - start_position,
-            ))
+/// `*{a:b}` names the variable `a:b` if there is one, otherwise `a` written in the format `b`
+fn block_path<'b>(
+    value_renderer: &impl ValueRenderer,
+    block: &'b ProcessingBlock,
+    iteration_count: usize,
+) -> (Cow<'b, str>, Option<&'b str>) {
+    match block.unformatted_path() {
+        Some(path) if value_renderer.has_variable(&path, iteration_count) => {
+            (Cow::Owned(path), None)
         }
+        _ => (Cow::Borrowed(&block.var_path), block.format.as_deref()),
     }
+}
 
-    fn return_variable_name(
-        template: &str,
-        start_position: usize,
-        relative_index: usize,
-    ) -> Result<(String, usize), TemplateRenderError> {
-        if relative_index == 1 {
-            EmptyProcessingBlockSnafu {
-                start_at: start_position,
-            }
-            .fail()
-        } else {
-            let end = start_position + relative_index - 1;
-            Ok((
-                template[start_position..=end].trim().to_owned(),
-                relative_index,
-            ))
+/// Template files are read through the context, as its directory and policy apply
+fn load_template(
+    templates: &TemplateCache,
+    config: &TemplateRendererConfig,
+    template: &TemplateValue,
+    context: &ProcessingContext,
+) -> Result<LoadedTemplate, TemplateRenderError> {
+    match template {
+        TemplateValue::RawValue(template) => {
+            Ok(LoadedTemplate::Compiled(templates.text(config, template)))
+        }
+        TemplateValue::Quote(file_path) => {
+            let full_file_path = context
+                .resolve_filename(file_path)
+                .context(TemplateAccessDeniedSnafu)?;
+
+            templates
+                .file(config, file_path, &full_file_path, || {
+                    context.open_file(&full_file_path)
+                })
+                .context(InvalidTemplateFileSnafu {
+                    file_name: file_path,
+                })
         }
     }
 }
@@ -248,3 +557,96 @@ impl<'a> Write for TemplateRenderer<'a> {
         self.output.flush()
     }
 }
+
+/// Output of a part of the template, possibly ending with an error
+type RenderedOutput = (Vec<u8>, Result<(), TemplateRenderError>);
+
+enum RenderedPart<'scope> {
+    Done(Vec<u8>, Result<(), TemplateRenderError>),
+    Rendering(ScopedJoinHandle<'scope, RenderedOutput>),
+}
+
+impl RenderedPart<'_> {
+    fn join(self) -> RenderedOutput {
+        match self {
+            RenderedPart::Done(buffer, result) => (buffer, result),
+            RenderedPart::Rendering(handle) => handle
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic)),
+        }
+    }
+}
+
+/// Everything the threads of a concurrent render share
+struct ConcurrentRender<'env, 'f, TValueRenderer> {
+    config: TemplateRendererConfig,
+    files: &'env Mutex<Option<&'f mut dyn OutputFiles>>,
+    jobs: Arc<JobSlots>,
+    templates: Arc<TemplateCache>,
+    routed_files: Arc<Mutex<HashSet<String>>>,
+    output_format: OutputFormat,
+    escape: EscapeMode,
+    context: Arc<ProcessingContext>,
+    value_renderer: &'env TValueRenderer,
+}
+
+impl<'env, TValueRenderer: ValueRenderer + Sync> ConcurrentRender<'env, '_, TValueRenderer> {
+    /// Adds the parts of one iteration of the template, independent values are started on
+    /// other threads and all others are rendered right away
+    fn collect<'scope>(
+        &'scope self,
+        scope: &'scope Scope<'scope, 'env>,
+        loaded: LoadedTemplate,
+        iteration_count: usize,
+        parts: &mut Vec<RenderedPart<'scope>>,
+    ) -> Result<(), TemplateRenderError> {
+        loaded.for_each_segment(&self.config, |segment| {
+            match segment {
+                TemplateSegment::Text(text) => {
+                    parts.push(RenderedPart::Done(text.to_vec(), Ok(())))
+                }
+                TemplateSegment::Block(block) => {
+                    let (var_path, _) = block_path(self.value_renderer, block, iteration_count);
+                    if self
+                        .value_renderer
+                        .is_independent(&var_path, iteration_count)
+                        && self.jobs.try_acquire()
+                    {
+                        let block = block.clone();
+                        parts.push(RenderedPart::Rendering(scope.spawn(move || {
+                            let rendered = self.render_block(&block, iteration_count);
+                            self.jobs.release();
+                            rendered
+                        })));
+                    } else {
+                        let (buffer, result) = self.render_block(block, iteration_count);
+                        parts.push(RenderedPart::Done(buffer, Ok(())));
+                        result?;
+                    }
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    fn render_block(&self, block: &ProcessingBlock, iteration_count: usize) -> RenderedOutput {
+        let mut buffer = Vec::<u8>::new();
+        let mut files = SharedOutputFiles(self.files);
+        let mut renderer = TemplateRenderer {
+            config: self.config.clone(),
+            output: &mut buffer,
+            files: Some(&mut files),
+            jobs: Some(Arc::clone(&self.jobs)),
+            templates: Arc::clone(&self.templates),
+            routed_files: Arc::clone(&self.routed_files),
+            output_format: self.output_format,
+            escape: self.escape,
+        };
+        let result = renderer
+            .render_block(self.value_renderer, block, iteration_count)
+            .context(ValueRenderingFailedSnafu);
+
+        (buffer, result)
+    }
+}
@@ -0,0 +1,278 @@
+use std::borrow::Cow;
+use std::io::{BufRead, ErrorKind, Read, Write};
+
+use crate::config::model::{Newline, OutputFormat, TextEncoding};
+
+/// Writes UTF-8 text with the newlines, encoding and byte order mark of an `OutputFormat`.
+/// Text written as rendered (UTF-8, `preserve`, no BOM) is passed through unchanged.
+///
+/// A `\r` at the end of a write and characters split between writes are held back until
+/// the rest arrives, `flush` writes what is left at the end of the output.
+pub struct EncodedWriter<W: Write> {
+    inner: W,
+    newline: Newline,
+    encoding: TextEncoding,
+    /// Written in front of the first text
+    bom_pending: bool,
+    /// A `\r` whose following `\n` might still come (`lf` only)
+    held_cr: bool,
+    /// The last byte written was a `\r` (`crlf` only)
+    after_cr: bool,
+    /// Beginning of a character whose remaining bytes were not written yet
+    incomplete: Vec<u8>,
+}
+
+impl<W: Write> EncodedWriter<W> {
+    pub fn new(inner: W, format: &OutputFormat) -> Self {
+        let encoding = format.encoding.unwrap_or_default();
+        EncodedWriter {
+            inner,
+            newline: format.newline.unwrap_or_default(),
+            encoding,
+            bom_pending: format.bom.unwrap_or(false) && !byte_order_mark(encoding).is_empty(),
+            held_cr: false,
+            after_cr: false,
+            incomplete: vec![],
+        }
+    }
+
+    fn write_bom(&mut self) -> std::io::Result<()> {
+        if self.bom_pending {
+            self.bom_pending = false;
+            self.inner.write_all(byte_order_mark(self.encoding))?;
+        }
+
+        Ok(())
+    }
+
+    fn convert_newlines<'t>(&mut self, text: &'t [u8]) -> Cow<'t, [u8]> {
+        match self.newline {
+            Newline::Preserve => Cow::Borrowed(text),
+            Newline::Lf => {
+                let mut converted = Vec::with_capacity(text.len() + 1);
+                for &byte in text {
+                    if std::mem::take(&mut self.held_cr) && byte != b'\n' {
+                        converted.push(b'\r');
+                    }
+                    match byte {
+                        b'\r' => self.held_cr = true,
+                        byte => converted.push(byte),
+                    }
+                }
+                Cow::Owned(converted)
+            }
+            Newline::Crlf => {
+                let mut converted = Vec::with_capacity(text.len() + text.len() / 16);
+                for &byte in text {
+                    if byte == b'\n' && !self.after_cr {
+                        converted.push(b'\r');
+                    }
+                    converted.push(byte);
+                    self.after_cr = byte == b'\r';
+                }
+                Cow::Owned(converted)
+            }
+        }
+    }
+
+    fn write_encoded(&mut self, text: &[u8]) -> std::io::Result<()> {
+        if self.encoding == TextEncoding::Utf8 {
+            return self.inner.write_all(text);
+        }
+
+        let mut pending = std::mem::take(&mut self.incomplete);
+        pending.extend_from_slice(text);
+        let valid = match std::str::from_utf8(&pending) {
+            Ok(valid) => valid,
+            Err(error) if error.error_len().is_none() => {
+                self.incomplete = pending[error.valid_up_to()..].to_vec();
+                std::str::from_utf8(&pending[..error.valid_up_to()]).unwrap_or_default()
+            }
+            Err(error) => return Err(std::io::Error::new(ErrorKind::InvalidData, error)),
+        };
+
+        let encoded = encode(valid, self.encoding)?;
+        self.inner.write_all(&encoded)
+    }
+}
+
+impl<W: Write> Write for EncodedWriter<W> {
+    /// Always takes the whole buffer: converted text can't be handed back partially
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.write_bom()?;
+        if self.newline == Newline::Preserve && self.encoding == TextEncoding::Utf8 {
+            self.inner.write_all(buf)?;
+        } else {
+            let converted = self.convert_newlines(buf);
+            self.write_encoded(&converted)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.write_bom()?;
+        if std::mem::take(&mut self.held_cr) {
+            self.write_encoded(b"\r")?;
+        }
+        if !self.incomplete.is_empty() {
+            return Err(std::io::Error::new(
+                ErrorKind::InvalidData,
+                "Output ends within a UTF-8 character",
+            ));
+        }
+
+        self.inner.flush()
+    }
+}
+
+fn byte_order_mark(encoding: TextEncoding) -> &'static [u8] {
+    match encoding {
+        TextEncoding::Utf8 => b"\xEF\xBB\xBF",
+        TextEncoding::Utf16Le => b"\xFF\xFE",
+        TextEncoding::Utf16Be => b"\xFE\xFF",
+        TextEncoding::Latin1 => b"",
+    }
+}
+
+fn encode(text: &str, encoding: TextEncoding) -> std::io::Result<Vec<u8>> {
+    match encoding {
+        TextEncoding::Utf8 => Ok(text.as_bytes().to_vec()),
+        TextEncoding::Utf16Le => Ok(text.encode_utf16().flat_map(u16::to_le_bytes).collect()),
+        TextEncoding::Utf16Be => Ok(text.encode_utf16().flat_map(u16::to_be_bytes).collect()),
+        TextEncoding::Latin1 => text
+            .chars()
+            .map(|c| {
+                u8::try_from(c).map_err(|_| {
+                    std::io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!("'{}' (U+{:04X}) cannot be written as Latin-1", c, c as u32),
+                    )
+                })
+            })
+            .collect(),
+    }
+}
+
+/// Reads text as UTF-8. The encoding is `declared`, or detected by the byte order mark
+/// and UTF-8 without one. A byte order mark matching the encoding is skipped.
+pub(crate) fn decoded(
+    mut input: Box<dyn BufRead>,
+    declared: Option<TextEncoding>,
+) -> std::io::Result<Box<dyn BufRead>> {
+    let beginning = input.fill_buf()?;
+    let detected = [
+        TextEncoding::Utf8,
+        TextEncoding::Utf16Le,
+        TextEncoding::Utf16Be,
+    ]
+    .into_iter()
+    .find(|encoding| beginning.starts_with(byte_order_mark(*encoding)));
+
+    let encoding = declared.or(detected).unwrap_or_default();
+    if detected == Some(encoding) {
+        input.consume(byte_order_mark(encoding).len());
+    }
+
+    match encoding {
+        TextEncoding::Utf8 => Ok(input),
+        encoding => Ok(Box::new(DecodingReader {
+            input,
+            encoding,
+            undecoded: vec![],
+            decoded: vec![],
+            position: 0,
+        })),
+    }
+}
+
+/// Turns UTF-16 or Latin-1 input into UTF-8
+struct DecodingReader {
+    input: Box<dyn BufRead>,
+    encoding: TextEncoding,
+    /// Part of a character at the end of the last chunk
+    undecoded: Vec<u8>,
+    /// The last chunk as UTF-8, read up to `position`
+    decoded: Vec<u8>,
+    position: usize,
+}
+
+impl DecodingReader {
+    /// The next decoded chunk, empty at the end of the input
+    fn decode_chunk(&mut self) -> std::io::Result<String> {
+        loop {
+            let chunk = self.input.fill_buf()?;
+            if chunk.is_empty() {
+                if self.undecoded.is_empty() {
+                    return Ok(String::new());
+                }
+                return Err(std::io::Error::new(
+                    ErrorKind::UnexpectedEof,
+                    "Input ends within a UTF-16 character",
+                ));
+            }
+
+            let mut bytes = std::mem::take(&mut self.undecoded);
+            bytes.extend_from_slice(chunk);
+            let length = chunk.len();
+            self.input.consume(length);
+
+            let decoded = match self.encoding {
+                TextEncoding::Latin1 => bytes.iter().map(|&byte| char::from(byte)).collect(),
+                TextEncoding::Utf16Le => self.decode_utf16(bytes, u16::from_le_bytes)?,
+                TextEncoding::Utf16Be => self.decode_utf16(bytes, u16::from_be_bytes)?,
+                TextEncoding::Utf8 => String::from_utf8(bytes)
+                    .map_err(|error| std::io::Error::new(ErrorKind::InvalidData, error))?,
+            };
+            if !decoded.is_empty() {
+                return Ok(decoded);
+            }
+        }
+    }
+
+    /// Keeps an odd byte and a trailing high surrogate for the next chunk
+    fn decode_utf16(
+        &mut self,
+        mut bytes: Vec<u8>,
+        unit: fn([u8; 2]) -> u16,
+    ) -> std::io::Result<String> {
+        let mut complete = bytes.len() - bytes.len() % 2;
+        if complete >= 2 {
+            let last = unit([bytes[complete - 2], bytes[complete - 1]]);
+            if (0xD800..0xDC00).contains(&last) {
+                complete -= 2;
+            }
+        }
+        self.undecoded = bytes.split_off(complete);
+
+        char::decode_utf16(bytes.chunks_exact(2).map(|pair| unit([pair[0], pair[1]])))
+            .collect::<Result<String, _>>()
+            .map_err(|error| std::io::Error::new(ErrorKind::InvalidData, error))
+    }
+}
+
+impl Read for DecodingReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let available = self.fill_buf()?;
+        let length = available.len().min(buf.len());
+        buf[..length].copy_from_slice(&available[..length]);
+        self.consume(length);
+
+        Ok(length)
+    }
+}
+
+impl BufRead for DecodingReader {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        if self.position == self.decoded.len() {
+            self.decoded = self.decode_chunk()?.into_bytes();
+            self.position = 0;
+        }
+
+        Ok(&self.decoded[self.position..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.position = (self.position + amount).min(self.decoded.len());
+    }
+}
@@ -0,0 +1,313 @@
+use std::fmt::Write;
+use std::time::SystemTime;
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset, NaiveDate, NaiveDateTime, Utc};
+
+use crate::rendering::{InvalidFormatSnafu, ValueRenderError};
+
+/// Alignment of a value within its width
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// A format like Rust's `{:>8.2}`: `[[fill]align][+][0][width][.precision]`
+struct FormatSpec {
+    fill: char,
+    align: Option<Align>,
+    sign: bool,
+    zero: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+enum Number {
+    Integer(i64),
+    Float(f64),
+}
+
+/// The value in the given format. Formats containing a `%` are `strftime` patterns for
+/// dates, all others are like Rust's format specs: `.2` for two decimals, `03` to pad
+/// with zeros, `>8` or `*^8` to align within a width and `+` to always show the sign.
+pub(crate) fn format_value(value: &str, format: &str) -> Result<String, ValueRenderError> {
+    let fail = |reason: &str| {
+        InvalidFormatSnafu {
+            value,
+            format,
+            reason,
+        }
+        .fail()
+    };
+
+    if format.contains('%') {
+        let Some(date_time) = parse_date_time(value) else {
+            return fail("not a date");
+        };
+        let items = StrftimeItems::new(format).collect::<Vec<_>>();
+        if items.iter().any(|item| matches!(item, Item::Error)) {
+            return fail("invalid date format");
+        }
+
+        let mut formatted = String::new();
+        if write!(formatted, "{}", date_time.format_with_items(items.iter())).is_err() {
+            return fail("date can't be written in this format");
+        }
+        return Ok(formatted);
+    }
+
+    let Some(spec) = FormatSpec::parse(format) else {
+        return fail("unknown format");
+    };
+
+    let number = Number::parse(value);
+    if !(spec.sign || spec.zero || spec.precision.is_some()) {
+        let align = if number.is_some() {
+            Align::Right
+        } else {
+            Align::Left
+        };
+        return Ok(spec.pad(value, "", align));
+    }
+
+    let (negative, digits) = match (number, spec.precision) {
+        (Some(Number::Integer(integer)), None | Some(0)) => {
+            (integer < 0, integer.unsigned_abs().to_string())
+        }
+        // not through f64, which can't hold every i64 exactly
+        (Some(Number::Integer(integer)), Some(precision)) => (
+            integer < 0,
+            format!("{}.{}", integer.unsigned_abs(), "0".repeat(precision)),
+        ),
+        // the digits as written, `to_string` would turn `1e3` into `1000`
+        (Some(Number::Float(float)), None) => {
+            let text = value.trim();
+            let unsigned = text.strip_prefix(['+', '-']).unwrap_or(text).to_owned();
+            (float.is_sign_negative(), unsigned)
+        }
+        (Some(Number::Float(float)), Some(precision)) => (
+            float.is_sign_negative(),
+            format!("{:.*}", precision, float.abs()),
+        ),
+        (None, _) => return fail("not a number"),
+    };
+    let sign = match (negative, spec.sign) {
+        (true, _) => "-",
+        (false, true) => "+",
+        (false, false) => "",
+    };
+
+    if spec.zero && spec.align.is_none() {
+        let zeros = spec
+            .width
+            .saturating_sub(sign.len() + digits.chars().count());
+        return Ok(format!("{}{}{}", sign, "0".repeat(zeros), digits));
+    }
+
+    Ok(spec.pad(&digits, sign, Align::Right))
+}
+
+/// Whether `format_value` understands the format, so `*{a:b}` with another text after the
+/// `:` stays the variable path `a:b`
+pub(crate) fn is_format(format: &str) -> bool {
+    if format.contains('%') {
+        return !StrftimeItems::new(format).any(|item| matches!(item, Item::Error));
+    }
+
+    FormatSpec::parse(format).is_some()
+}
+
+impl FormatSpec {
+    fn parse(format: &str) -> Option<Self> {
+        let mut spec = FormatSpec {
+            fill: ' ',
+            align: None,
+            sign: false,
+            zero: false,
+            width: 0,
+            precision: None,
+        };
+
+        let mut rest = format;
+        let mut chars = rest.chars();
+        match (chars.next(), chars.next().and_then(Align::from_char)) {
+            (Some(fill), Some(align)) => {
+                spec.fill = fill;
+                spec.align = Some(align);
+                rest = &rest[fill.len_utf8() + 1..];
+            }
+            (Some(align), None) if Align::from_char(align).is_some() => {
+                spec.align = Align::from_char(align);
+                rest = &rest[1..];
+            }
+            _ => (),
+        }
+
+        if let Some(after_sign) = rest.strip_prefix('+') {
+            spec.sign = true;
+            rest = after_sign;
+        }
+        if let Some(after_zero) = rest.strip_prefix('0') {
+            spec.zero = true;
+            rest = after_zero;
+        }
+
+        let (width, after_width) = leading_number(rest);
+        spec.width = width.unwrap_or(0);
+        rest = after_width;
+
+        if let Some(after_point) = rest.strip_prefix('.') {
+            let (precision, after_precision) = leading_number(after_point);
+            spec.precision = Some(precision?);
+            rest = after_precision;
+        }
+
+        rest.is_empty().then_some(spec)
+    }
+
+    /// `sign` and `text` padded to the width, by default aligned as given
+    fn pad(&self, text: &str, sign: &str, default_align: Align) -> String {
+        let length = sign.chars().count() + text.chars().count();
+        let padding = self.width.saturating_sub(length);
+        let (before, after) = match self.align.unwrap_or(default_align) {
+            Align::Left => (0, padding),
+            Align::Center => (padding / 2, padding - padding / 2),
+            Align::Right => (padding, 0),
+        };
+
+        let fill = |count: usize| std::iter::repeat_n(self.fill, count).collect::<String>();
+        format!("{}{}{}{}", fill(before), sign, text, fill(after))
+    }
+}
+
+impl Align {
+    fn from_char(c: char) -> Option<Self> {
+        match c {
+            '<' => Some(Align::Left),
+            '^' => Some(Align::Center),
+            '>' => Some(Align::Right),
+            _ => None,
+        }
+    }
+}
+
+impl Number {
+    fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        text.parse::<i64>().map(Number::Integer).ok().or_else(|| {
+            // `f64` also reads `nan`, `inf` and `infinity`, which aren't numbers to format
+            text.parse::<f64>()
+                .ok()
+                .filter(|float| float.is_finite())
+                .map(Number::Float)
+        })
+    }
+}
+
+fn leading_number(text: &str) -> (Option<usize>, &str) {
+    let end = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+
+    (text[..end].parse().ok(), &text[end..])
+}
+
+/// Dates as written by `$now` (RFC 3339) and `$today`, date and time without offset (taken
+/// as UTC) or seconds since the Unix epoch
+fn parse_date_time(text: &str) -> Option<DateTime<FixedOffset>> {
+    let text = text.trim();
+    if let Ok(date_time) = DateTime::parse_from_rfc3339(text) {
+        return Some(date_time);
+    }
+
+    let naive = ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+        .into_iter()
+        .find_map(|pattern| NaiveDateTime::parse_from_str(text, pattern).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        });
+    if let Some(naive) = naive {
+        return Some(naive.and_utc().fixed_offset());
+    }
+
+    text.parse::<i64>()
+        .ok()
+        .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+        .map(|date_time| date_time.fixed_offset())
+}
+
+/// A point in time written like a date value: RFC 3339, date and time (UTC), a date or
+/// seconds since the Unix epoch
+pub fn parse_time(text: &str) -> Option<SystemTime> {
+    parse_date_time(text).map(SystemTime::from)
+}
+
+/// `$now` as RFC 3339 and `$today` as `YYYY-MM-DD`, both in UTC
+pub(crate) fn clock_values(time: SystemTime) -> (String, String) {
+    let date_time = DateTime::<Utc>::from(time);
+
+    (
+        date_time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+        date_time.format("%Y-%m-%d").to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn non_finite_values_are_not_numbers() {
+        for value in ["nan", "NaN", "inf", "-inf", "infinity", "+Infinity"] {
+            assert!(format_value(value, ".2").is_err(), "{}", value);
+            assert!(format_value(value, "+").is_err(), "{}", value);
+            assert_eq!(format_value(value, "5").unwrap(), format!("{value:<5}"));
+        }
+    }
+
+    #[test]
+    fn sign_and_zeros_keep_the_digits_of_floats() {
+        assert_eq!(format_value("1e3", "+").unwrap(), "+1e3");
+        assert_eq!(format_value("-1.50", "08").unwrap(), "-0001.50");
+        assert_eq!(format_value(" +0.1 ", "+").unwrap(), "+0.1");
+        assert_eq!(format_value("1e3", ".1").unwrap(), "1000.0");
+    }
+
+    #[test]
+    fn integers_keep_their_precision() {
+        assert_eq!(
+            format_value("9007199254740993", ".2").unwrap(),
+            "9007199254740993.00"
+        );
+        assert_eq!(format_value("-7", "+04").unwrap(), "-007");
+        assert_eq!(format_value("7", "+").unwrap(), "+7");
+    }
+
+    #[test]
+    fn values_align_within_their_width() {
+        assert_eq!(format_value("42", "5").unwrap(), "   42");
+        assert_eq!(format_value("ab", "5").unwrap(), "ab   ");
+        assert_eq!(format_value("3.14159", "*^9.2").unwrap(), "**3.14***");
+        assert_eq!(format_value("1.5", "<6.1").unwrap(), "1.5   ");
+    }
+
+    #[test]
+    fn dates_use_strftime_patterns() {
+        assert_eq!(
+            format_value("2024-02-29T13:05:00+01:00", "%d.%m.%Y %H:%M").unwrap(),
+            "29.02.2024 13:05"
+        );
+        assert_eq!(format_value("0", "%Y-%m-%d").unwrap(), "1970-01-01");
+        assert!(format_value("soon", "%Y").is_err());
+    }
+
+    #[test]
+    fn unknown_formats_stay_variable_paths() {
+        assert!(is_format(">8.2") && is_format("%Y") && is_format("+"));
+        assert!(!is_format("b") && !is_format(".") && !is_format("8x"));
+    }
+}
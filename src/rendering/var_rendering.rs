@@ -1,17 +1,24 @@
-use std::fs::File;
-use std::io::{BufRead, BufReader, Read, Write};
-use std::process::Command;
-use std::rc::Rc;
+use std::ffi::OsString;
+use std::io::{BufRead, ErrorKind, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 
-use snafu::ResultExt;
+use snafu::{OptionExt, ResultExt};
 
 use crate::{
-    config::model::RawValue,
+    config::model::{
+        ExecuteOptions, ExecuteTarget, QuoteStatement, RawValue, StderrHandling, TemplateValue,
+    },
     processing::{GlitterProcessor, ProcessingContext},
     rendering::{
-        ExecuteCommandFailedSnafu, ExecuteResultInvalidSnafu, FailedReadingTextSnafu,
-        FailedWritingTextSnafu, InvalidCalculateCallSnafu, RenderCommandFailedSnafu,
-        TemplateRenderer, ValueRenderError,
+        text_encoding::decoded, AccessDeniedSnafu, ExecutableNotFoundSnafu,
+        ExecuteCommandFailedSnafu, ExecuteExitedWithFailureSnafu, ExecuteResultInvalidSnafu,
+        ExecuteTimedOutSnafu, FailedReadingTextSnafu, FailedWritingOutputFileSnafu,
+        FailedWritingTextSnafu, MacroFailedSnafu, RenderCommandFailedSnafu,
+        RenderResultInvalidSnafu, TemplateRenderer, ValueRenderError,
     },
 };
 
@@ -33,13 +40,15 @@ impl From<RawValue> for RenderableRawValue {
 impl RenderableVariable for RenderableRawValue {
     fn render(&self, output: &mut TemplateRenderer) -> Result<(), ValueRenderError> {
         match &self.value {
-            RawValue::Boolean(true) => output.write(b"true").context(FailedWritingTextSnafu)?,
-            RawValue::Boolean(false) => output.write(b"false").context(FailedWritingTextSnafu)?,
+            RawValue::Boolean(true) => output.write_all(b"true").context(FailedWritingTextSnafu)?,
+            RawValue::Boolean(false) => {
+                output.write_all(b"false").context(FailedWritingTextSnafu)?
+            }
             RawValue::Integer(value) => output
-                .write(format!("{}", value).as_bytes())
+                .write_all(format!("{}", value).as_bytes())
                 .context(FailedWritingTextSnafu)?,
             RawValue::Float(value) | RawValue::String(value) => output
-                .write(value.as_bytes())
+                .write_all(value.as_bytes())
                 .context(FailedWritingTextSnafu)?,
         };
 
@@ -52,47 +61,43 @@ impl RenderableVariable for RenderableRawValue {
 }
 
 pub(crate) struct RenderableQuote {
-    file: String,
-    context: Rc<ProcessingContext>,
+    quote: QuoteStatement,
+    context: Arc<ProcessingContext>,
 }
 
 impl RenderableQuote {
-    pub(crate) fn from(file: String, context: Rc<ProcessingContext>) -> Self {
-        RenderableQuote { file, context }
+    pub(crate) fn from(quote: QuoteStatement, context: Arc<ProcessingContext>) -> Self {
+        RenderableQuote { quote, context }
+    }
+
+    /// The quoted file as UTF-8 and its full path
+    fn open(&self) -> Result<(Box<dyn BufRead>, String), ValueRenderError> {
+        let fullname = self
+            .context
+            .resolve_filename(&self.quote.file)
+            .context(AccessDeniedSnafu)?;
+
+        let input = self
+            .context
+            .open_file(&fullname)
+            .and_then(|input| decoded(input, self.quote.encoding))
+            .context(FailedReadingTextSnafu {
+                input_file: fullname.clone(),
+            })?;
+
+        Ok((input, fullname))
     }
 }
 
 impl RenderableVariable for RenderableQuote {
     fn render(&self, output: &mut TemplateRenderer) -> Result<(), ValueRenderError> {
-        let fullname = self.context.resolve_filename(&self.file);
-
-        let input = File::open(&fullname).context(FailedReadingTextSnafu {
-            input_file: fullname.clone(),
-        })?;
-        let buffered = BufReader::new(input);
-
-        for line in buffered.lines() {
-            output
-                .write(
-                    line.context(FailedReadingTextSnafu {
-                        input_file: fullname.clone(),
-                    })?
-                    .as_bytes(),
-                )
-                .context(FailedWritingTextSnafu)?;
-            output.write(b"\n").context(FailedWritingTextSnafu)?;
-        }
+        let (mut buffered, fullname) = self.open()?;
 
-        Ok(())
+        copy_lines(&mut buffered, output, &fullname)
     }
 
     fn calculate(&self) -> Result<RawValue, ValueRenderError> {
-        let fullname = self.context.resolve_filename(&self.file);
-
-        let input = File::open(&fullname).context(FailedReadingTextSnafu {
-            input_file: fullname,
-        })?;
-        let mut buffered = BufReader::new(input);
+        let (mut buffered, _) = self.open()?;
         let mut result = String::new();
 
         buffered
@@ -104,30 +109,465 @@ impl RenderableVariable for RenderableQuote {
 }
 
 pub(crate) struct RenderableExecutionResult {
-    executable: String,
+    target: ExecuteTarget,
     arguments: Vec<RawValue>,
-    context: Rc<ProcessingContext>,
+    environment: ExecuteEnvironment,
+    options: ExecuteOptions,
+    context: Arc<ProcessingContext>,
 }
 
+/// The already resolved input of a command
+pub(crate) struct ExecuteEnvironment {
+    pub(crate) stdin: Option<String>,
+    pub(crate) env: Vec<(String, String)>,
+    /// Full path of the working directory
+    pub(crate) cwd: Option<String>,
+    pub(crate) clear_env: bool,
+}
+
+/// How many lines of stderr are kept for the error message of a failed command
+const STDERR_TAIL_LINES: usize = 10;
+
+/// Size of the pieces command output is copied in
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+/// How much of a streamed output is kept, to take the last lines of merged stderr from
+const STREAMED_TAIL_BYTES: usize = 64 * 1024;
+
 impl RenderableExecutionResult {
     pub(crate) fn from(
-        executable: String,
+        target: ExecuteTarget,
         arguments: Vec<RawValue>,
-        context: Rc<ProcessingContext>,
+        environment: ExecuteEnvironment,
+        options: ExecuteOptions,
+        context: Arc<ProcessingContext>,
     ) -> Self {
         RenderableExecutionResult {
-            executable,
+            target,
             arguments,
+            environment,
+            options,
             context,
         }
     }
+
+    /// The command as written, for messages
+    pub(crate) fn describe(&self) -> String {
+        self.target.to_string()
+    }
+
+    /// The program to start and the arguments to pass to it
+    fn command_line(&self) -> Result<(PathBuf, Vec<String>), ValueRenderError> {
+        let arguments = self.arguments.iter().map(|v| v.to_string());
+
+        match &self.target {
+            ExecuteTarget::Shell(command_line) => Ok((
+                PathBuf::from(SHELL),
+                // `glitter` becomes `$0`, so the arguments start at `$1`
+                [
+                    String::from("-c"),
+                    command_line.clone(),
+                    String::from("glitter"),
+                ]
+                .into_iter()
+                .chain(arguments)
+                .collect(),
+            )),
+            ExecuteTarget::Executable(executable) => {
+                let next_to_file = Path::new(&self.context.join_path(executable)).is_file();
+                let program = if is_bare_name(executable) && !next_to_file {
+                    self.lookup_in_path(executable)
+                        .context(ExecutableNotFoundSnafu { executable })?
+                } else {
+                    // a file like any other, so it has to lie within the root
+                    let relative_to_file = self
+                        .context
+                        .resolve_filename(executable)
+                        .context(AccessDeniedSnafu)?;
+                    // absolute, so a changed working directory doesn't change which executable is started
+                    std::path::absolute(relative_to_file).context(ExecuteCommandFailedSnafu)?
+                };
+
+                Ok((program, arguments.collect()))
+            }
+        }
+    }
+
+    /// Searches the `PATH` defined in `env`, or else the one of glitter
+    fn lookup_in_path(&self, name: &str) -> Option<PathBuf> {
+        let path = match self.environment.env.iter().find(|(key, _)| key == "PATH") {
+            Some((_, path)) => OsString::from(path),
+            None => std::env::var_os("PATH")?,
+        };
+
+        std::env::split_paths(&path)
+            .map(|directory| directory.join(name))
+            .find(|candidate| is_executable(candidate))
+    }
+
+    fn spawn(&self) -> Result<RunningCommand, ValueRenderError> {
+        let (program, arguments) = self.command_line()?;
+        let written = match &self.target {
+            ExecuteTarget::Executable(executable) => executable.as_str(),
+            ExecuteTarget::Shell(_) => "sh",
+        };
+        self.context
+            .policy
+            .check_executable(written, &program)
+            .context(AccessDeniedSnafu)?;
+
+        self.spawn_program(program, arguments)
+            .context(ExecuteCommandFailedSnafu)
+    }
+
+    fn spawn_program(
+        &self,
+        program: PathBuf,
+        arguments: Vec<String>,
+    ) -> std::io::Result<RunningCommand> {
+        let mut command = Command::new(program);
+        command
+            .args(arguments)
+            .stdin(if self.environment.stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            });
+
+        if self.environment.clear_env {
+            command.env_clear();
+        }
+        for (name, value) in &self.environment.env {
+            command.env(name, value);
+        }
+        if let Some(cwd) = &self.environment.cwd {
+            command.current_dir(cwd);
+        }
+        // in a process group of its own, so a timeout ends everything the command started
+        #[cfg(unix)]
+        if self.options.timeout.is_some() {
+            use std::os::unix::process::CommandExt;
+            command.process_group(0);
+        }
+
+        let mut merged_output = None;
+        match self.options.stderr {
+            StderrHandling::Capture => {
+                command.stdout(Stdio::piped()).stderr(Stdio::piped());
+            }
+            StderrHandling::Inherit => {
+                command.stdout(Stdio::piped()).stderr(Stdio::inherit());
+            }
+            StderrHandling::Merge => {
+                let (reader, writer) = std::io::pipe()?;
+                command.stdout(writer.try_clone()?).stderr(writer);
+                merged_output = Some(reader);
+            }
+        }
+
+        let mut child = command.spawn()?;
+        // the command keeps the write end of a merged pipe open until it is dropped
+        drop(command);
+
+        let stdout: Box<dyn Read + Send> = match merged_output {
+            Some(reader) => Box::new(reader),
+            None => Box::new(child.stdout.take().expect("stdout is piped")),
+        };
+        let stderr = child.stderr.take().map(read_in_background);
+
+        if let (Some(mut stdin), Some(input)) = (child.stdin.take(), &self.environment.stdin) {
+            let input = input.clone().into_bytes();
+            // written in the background, so a command not reading all of its input can't block us.
+            // A failing write means the command closed its input early, which is its own business
+            std::thread::spawn(move || {
+                let _ = stdin.write_all(&input);
+            });
+        }
+
+        Ok(RunningCommand {
+            child,
+            stdout,
+            stderr,
+        })
+    }
+}
+
+const SHELL: &str = "/bin/sh";
+
+/// Names without any directory part, like `uname`
+pub(crate) fn is_bare_name(executable: &str) -> bool {
+    let mut components = Path::new(executable).components();
+    matches!(
+        (components.next(), components.next()),
+        (Some(Component::Normal(_)), None)
+    )
+}
+
+#[cfg(unix)]
+pub(crate) fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+
+    path.metadata()
+        .is_ok_and(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+pub(crate) fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+struct RunningCommand {
+    child: Child,
+    /// Including stderr, if merged
+    stdout: Box<dyn Read + Send>,
+    stderr: Option<JoinHandle<std::io::Result<Vec<u8>>>>,
+}
+
+fn read_in_background<R: Read + Send + 'static>(
+    mut source: R,
+) -> JoinHandle<std::io::Result<Vec<u8>>> {
+    std::thread::spawn(move || {
+        let mut content = Vec::new();
+        source.read_to_end(&mut content).map(|_| content)
+    })
+}
+
+fn collect(reader: JoinHandle<std::io::Result<Vec<u8>>>) -> std::io::Result<Vec<u8>> {
+    reader.join().unwrap_or_else(|_| Ok(vec![]))
+}
+
+/// Stderr only ends up in messages, so a failing read keeps what was read before
+fn collect_stderr(reader: JoinHandle<std::io::Result<Vec<u8>>>) -> Vec<u8> {
+    collect(reader).unwrap_or_default()
+}
+
+/// Kills the command and the processes it started, which share its process group
+fn kill_process_group(child: &mut Child) -> std::io::Result<()> {
+    #[cfg(unix)]
+    if let Ok(group) = libc::pid_t::try_from(child.id()) {
+        // SAFETY: only sends a signal, the group id is the pid of the command (see `spawn`)
+        if unsafe { libc::kill(-group, libc::SIGKILL) } == 0 {
+            return Ok(());
+        }
+    }
+
+    child.kill()
+}
+
+/// Waits for a reader of a killed command for a short while, as processes which left its
+/// process group may still hold the pipe. A reader still blocked then is left behind.
+fn join_within(reader: JoinHandle<std::io::Result<Vec<u8>>>, limit: Duration) {
+    let deadline = Instant::now() + limit;
+    while !reader.is_finished() && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(5));
+    }
+    if reader.is_finished() {
+        let _ = reader.join();
+    }
+}
+
+/// How long the readers of a command killed after its timeout are waited for
+const KILLED_READER_LIMIT: Duration = Duration::from_millis(200);
+
+/// Waits for the child to exit. `None` if it has been killed after the timeout expired
+fn wait_for(child: &mut Child, timeout: Option<Duration>) -> std::io::Result<Option<ExitStatus>> {
+    let Some(timeout) = timeout else {
+        return child.wait().map(Some);
+    };
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+
+        let now = Instant::now();
+        if now >= deadline {
+            kill_process_group(child)?;
+            child.wait()?;
+            return Ok(None);
+        }
+        std::thread::sleep((deadline - now).min(Duration::from_millis(10)));
+    }
+}
+
+fn tail(output: &[u8]) -> String {
+    let output = String::from_utf8_lossy(output);
+    let lines = output.lines().collect::<Vec<_>>();
+    lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n")
+}
+
+impl RenderableExecutionResult {
+    /// Copies the output into the renderer while the command runs. Commands with a `timeout`
+    /// or `trim` are not streamed, so the value is the same as the one of `calculate`.
+    fn can_stream(&self) -> bool {
+        self.options.timeout.is_none() && !self.options.trim
+    }
+
+    fn stream(&self, output: &mut TemplateRenderer) -> Result<(), ValueRenderError> {
+        let mut running = self.spawn()?;
+
+        let mut output_tail = vec![];
+        if let Err(error) = copy_output(
+            &mut running.stdout,
+            output,
+            &mut output_tail,
+            &self.describe(),
+        ) {
+            let _ = running.child.kill();
+            let _ = running.child.wait();
+            return Err(error);
+        }
+
+        let status = running.child.wait().context(ExecuteCommandFailedSnafu)?;
+        let stderr = running.stderr.map(collect_stderr);
+
+        self.check_status(status, stderr.as_deref(), &output_tail)
+    }
+
+    /// Fails for a non-zero exit code, unless failures are allowed
+    fn check_status(
+        &self,
+        status: ExitStatus,
+        stderr: Option<&[u8]>,
+        output: &[u8],
+    ) -> Result<(), ValueRenderError> {
+        if status.success() || self.options.allow_failure {
+            return Ok(());
+        }
+
+        let stderr_tail = match (stderr, self.options.stderr) {
+            (Some(stderr), _) => tail(stderr),
+            (None, StderrHandling::Merge) => tail(output),
+            (None, _) => String::new(),
+        };
+
+        ExecuteExitedWithFailureSnafu {
+            executable: self.describe(),
+            exit_code: status.code(),
+            stderr_tail,
+        }
+        .fail()
+    }
+}
+
+/// Copies everything the command writes to stdout, keeping the end of it in `tail`.
+/// The output is checked to be UTF-8 like the one of `calculate`: a character split between
+/// two reads is held back until it is complete, invalid bytes fail the value.
+fn copy_output(
+    input: &mut dyn Read,
+    output: &mut TemplateRenderer,
+    tail: &mut Vec<u8>,
+    executable: &str,
+) -> Result<(), ValueRenderError> {
+    let mut buffer = vec![0; STREAM_CHUNK_SIZE];
+    let mut pending = vec![];
+    loop {
+        let read = match input.read(&mut buffer) {
+            Ok(0) if pending.is_empty() => return Ok(()),
+            // the output ends within a character
+            Ok(0) => {
+                return String::from_utf8(pending)
+                    .map(drop)
+                    .context(ExecuteResultInvalidSnafu)
+            }
+            Ok(read) => read,
+            Err(error) if error.kind() == ErrorKind::Interrupted => continue,
+            Err(error) => {
+                return Err(error).context(FailedReadingTextSnafu {
+                    input_file: executable,
+                })
+            }
+        };
+
+        pending.extend_from_slice(&buffer[..read]);
+        let valid = match std::str::from_utf8(&pending) {
+            Ok(_) => pending.len(),
+            Err(error) if error.error_len().is_none() => error.valid_up_to(),
+            Err(_) => {
+                return String::from_utf8(pending)
+                    .map(drop)
+                    .context(ExecuteResultInvalidSnafu)
+            }
+        };
+
+        output
+            .write_all(&pending[..valid])
+            .context(FailedWritingTextSnafu)?;
+
+        tail.extend_from_slice(&pending[..valid]);
+        if tail.len() > STREAMED_TAIL_BYTES {
+            tail.drain(..tail.len() - STREAMED_TAIL_BYTES);
+        }
+        pending.drain(..valid);
+    }
+}
+
+/// Copies a text file in chunks, with `\r\n` line ends turned into `\n` and a line end
+/// added to the last line, like reading it line by line would
+fn copy_lines(
+    input: &mut dyn BufRead,
+    output: &mut TemplateRenderer,
+    input_file: &str,
+) -> Result<(), ValueRenderError> {
+    let mut held_back_cr = false;
+    let mut ends_with_newline = true;
+
+    loop {
+        let chunk = input
+            .fill_buf()
+            .context(FailedReadingTextSnafu { input_file })?;
+        let Some(&last) = chunk.last() else {
+            break;
+        };
+
+        let mut start = 0;
+        if held_back_cr && chunk[0] != b'\n' {
+            output.write_all(b"\r").context(FailedWritingTextSnafu)?;
+        }
+        for index in 0..chunk.len() - 1 {
+            if chunk[index] == b'\r' && chunk[index + 1] == b'\n' {
+                output
+                    .write_all(&chunk[start..index])
+                    .context(FailedWritingTextSnafu)?;
+                start = index + 1;
+            }
+        }
+
+        held_back_cr = last == b'\r';
+        let end = if held_back_cr {
+            chunk.len() - 1
+        } else {
+            chunk.len()
+        };
+        output
+            .write_all(&chunk[start.min(end)..end])
+            .context(FailedWritingTextSnafu)?;
+        ends_with_newline = last == b'\n';
+
+        let length = chunk.len();
+        input.consume(length);
+    }
+
+    if held_back_cr {
+        output.write_all(b"\r").context(FailedWritingTextSnafu)?;
+    }
+    if !ends_with_newline {
+        output.write_all(b"\n").context(FailedWritingTextSnafu)?;
+    }
+
+    Ok(())
 }
 
 impl RenderableVariable for RenderableExecutionResult {
     fn render(&self, output: &mut TemplateRenderer) -> std::result::Result<(), ValueRenderError> {
+        if self.can_stream() {
+            return self.stream(output);
+        }
+
         if let RawValue::String(content) = self.calculate()? {
             output
-                .write(content.as_bytes())
+                .write_all(content.as_bytes())
                 .context(FailedWritingTextSnafu)?;
 
             Ok(())
@@ -137,43 +577,138 @@ impl RenderableVariable for RenderableExecutionResult {
     }
 
     fn calculate(&self) -> Result<RawValue, ValueRenderError> {
-        let fullname = self.context.resolve_filename(&self.executable);
-        let result = Command::new(fullname)
-            .args(
-                self.arguments
-                    .iter()
-                    .map(|v| v.to_string())
-                    .collect::<Vec<_>>(),
-            )
-            .output()
+        let mut running = self.spawn()?;
+        let stdout = read_in_background(running.stdout);
+
+        let Some(status) = wait_for(&mut running.child, self.options.timeout)
             .context(ExecuteCommandFailedSnafu)?
-            .stdout;
+        else {
+            join_within(stdout, KILLED_READER_LIMIT);
+            if let Some(stderr) = running.stderr {
+                join_within(stderr, KILLED_READER_LIMIT);
+            }
+            return ExecuteTimedOutSnafu {
+                executable: self.describe(),
+                timeout: self.options.timeout.unwrap_or_default(),
+            }
+            .fail();
+        };
 
-        Ok(RawValue::String(
-            String::from_utf8(result).context(ExecuteResultInvalidSnafu)?,
-        ))
+        let result = collect(stdout).context(FailedReadingTextSnafu {
+            input_file: self.describe(),
+        })?;
+        let stderr = running.stderr.map(collect_stderr);
+
+        self.check_status(status, stderr.as_deref(), &result)?;
+
+        let result = String::from_utf8(result).context(ExecuteResultInvalidSnafu)?;
+        if self.options.trim {
+            return Ok(RawValue::String(result.trim().to_owned()));
+        }
+
+        Ok(RawValue::String(result))
     }
 }
 
 pub(crate) struct SubRender {
     processor: GlitterProcessor,
+    output: Option<String>,
+}
+
+impl SubRender {
+    /// With an `output` the result is written into that file instead of being inlined
+    pub(crate) fn routed(processor: GlitterProcessor, output: Option<String>) -> Self {
+        SubRender { processor, output }
+    }
 }
 
 impl From<GlitterProcessor> for SubRender {
     fn from(processor: GlitterProcessor) -> Self {
-        SubRender { processor }
+        SubRender {
+            processor,
+            output: None,
+        }
     }
 }
 
 impl RenderableVariable for SubRender {
+    /// A routed sub-render writes its file when it is referenced first and is replaced by
+    /// the file name, like its calculated value
     fn render(&self, renderer: &mut TemplateRenderer) -> std::result::Result<(), ValueRenderError> {
+        if let Some(output) = &self.output {
+            if renderer.claim_routed_file(output) {
+                let mut file = renderer
+                    .create_output_file(
+                        output,
+                        self.processor.output_format(),
+                        self.processor.policy(),
+                    )
+                    .context(FailedWritingOutputFileSnafu { file_name: output })?;
+                self.processor
+                    .render(&mut renderer.redirect(&mut file))
+                    .context(RenderCommandFailedSnafu)?;
+                file.flush()
+                    .context(FailedWritingOutputFileSnafu { file_name: output })?;
+            }
+            return renderer
+                .write_all(output.as_bytes())
+                .context(FailedWritingTextSnafu);
+        }
+
         self.processor
             .render(renderer)
             .context(RenderCommandFailedSnafu)?;
         Ok(())
     }
 
+    /// The value of a routed sub-render is the name of its output file, which is only written
+    /// when the value is rendered into a template
+    fn calculate(&self) -> std::result::Result<RawValue, ValueRenderError> {
+        if let Some(output) = &self.output {
+            return Ok(RawValue::String(output.clone()));
+        }
+
+        let mut buffer = Vec::<u8>::new();
+        self.processor
+            .render(&mut TemplateRenderer::new(&mut buffer))
+            .context(RenderCommandFailedSnafu)?;
+
+        Ok(RawValue::String(
+            String::from_utf8(buffer).context(RenderResultInvalidSnafu)?,
+        ))
+    }
+}
+
+/// A macro bound to its arguments, rendered in the scope it was defined in
+pub(crate) struct MacroExpansion {
+    name: String,
+    processor: GlitterProcessor,
+    template: TemplateValue,
+}
+
+impl MacroExpansion {
+    pub(crate) fn new(name: String, processor: GlitterProcessor, template: TemplateValue) -> Self {
+        MacroExpansion {
+            name,
+            processor,
+            template,
+        }
+    }
+}
+
+impl RenderableVariable for MacroExpansion {
+    fn render(&self, renderer: &mut TemplateRenderer) -> std::result::Result<(), ValueRenderError> {
+        self.processor
+            .render_fragment(&self.template, renderer)
+            .context(MacroFailedSnafu { name: &self.name })
+    }
+
     fn calculate(&self) -> std::result::Result<RawValue, ValueRenderError> {
-        InvalidCalculateCallSnafu {}.fail()
+        let mut buffer = Vec::<u8>::new();
+        self.render(&mut TemplateRenderer::new(&mut buffer))?;
+
+        Ok(RawValue::String(
+            String::from_utf8(buffer).context(RenderResultInvalidSnafu)?,
+        ))
     }
 }